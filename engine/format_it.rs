@@ -1,8 +1,8 @@
 use super::module::{
-    Column, Effect, LoopType, Module, ModuleInterface, Note, Pattern, PlaybackMode, Row, Sample,
-    VolEffect,
+    Column, DuplicateCheck, Effect, Envelope, Instrument, LoopType, Module, ModuleInterface,
+    NewNoteAction, Note, Pattern, PlaybackMode, Row, Sample, VolEffect,
 };
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::{
     io::{self, Read, SeekFrom},
     slice,
@@ -287,6 +287,55 @@ impl ITPattern {
         }
         // println!("PATTERN END");
     }
+
+    pub fn to_packed_bytes(&self) -> Vec<u8> {
+        // Every column gets its mask written out, no "last value" compression
+        let mut pattern_bytes = Vec::<u8>::new();
+
+        for row in &self.rows {
+            for (channel_number, column) in row.iter().enumerate() {
+                let mut mask = 0u8;
+                if column.note != 120 {
+                    mask |= 1
+                };
+                if column.instrument != 0 {
+                    mask |= 2
+                };
+                if column.vol != 255 {
+                    mask |= 4
+                };
+                if column.effect != 0 || column.effect_value != 0 {
+                    mask |= 8
+                };
+
+                if mask == 0 {
+                    continue;
+                }
+
+                pattern_bytes.push((channel_number as u8 + 1) | 128);
+                pattern_bytes.push(mask);
+
+                if mask & 1 != 0 {
+                    pattern_bytes.push(column.note)
+                }
+                if mask & 2 != 0 {
+                    pattern_bytes.push(column.instrument)
+                }
+                if mask & 4 != 0 {
+                    pattern_bytes.push(column.vol)
+                }
+                if mask & 8 != 0 {
+                    pattern_bytes.push(column.effect);
+                    pattern_bytes.push(column.effect_value);
+                }
+            }
+
+            // End of row
+            pattern_bytes.push(0);
+        }
+
+        pattern_bytes
+    }
 }
 
 impl Default for ITModule {
@@ -495,6 +544,10 @@ impl ITModule {
 
             // 0130, 0182, 01D4
             for i in 0..3 as usize {
+                // Envelopes are fixed size (25 nodes + 1 byte padding) regardless of node_amount
+                reader
+                    .seek(SeekFrom::Start(*offset as u64 + 0x130 + i as u64 * 82))
+                    .unwrap();
                 let mut env = ITEnvelope::default();
 
                 env.flag = reader.read_u8().unwrap();
@@ -630,6 +683,482 @@ impl ITModule {
     }
 }
 
+impl ITModule {
+    pub fn save(&self, mut writer: impl io::Write) -> io::Result<()> {
+        // Song message and edit history aren't loaded, so they can't be written back.
+        // Compressed samples are loaded as their raw compressed bytes, writing those out
+        // as PCM would turn them into noise for good
+        if self.samples.iter().any(|s| s.flags & 0b1000 != 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compressed samples can't be saved",
+            ));
+        }
+
+        let patterns_bytes: Vec<Vec<u8>> =
+            self.patterns.iter().map(|p| p.to_packed_bytes()).collect();
+        // Lengths are stored in 16 bits and nothing is packed with last values
+        if patterns_bytes.iter().any(|b| b.len() > u16::MAX as usize) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a pattern is too big to be saved",
+            ));
+        }
+
+        // --- OFFSETS ---
+        let mut offset = 0xC0
+            + self.orders.len() as u32
+            + (self.instruments.len() + self.samples.len() + self.patterns.len()) as u32 * 4;
+
+        let mut instrument_offsets = Vec::<u32>::with_capacity(self.instruments.len());
+        for _ in &self.instruments {
+            instrument_offsets.push(offset);
+            offset += 554;
+        }
+
+        let mut sample_offsets = Vec::<u32>::with_capacity(self.samples.len());
+        for _ in &self.samples {
+            sample_offsets.push(offset);
+            offset += 80;
+        }
+
+        let mut pattern_offsets = Vec::<u32>::with_capacity(self.patterns.len());
+        for (pattern, bytes) in self.patterns.iter().zip(&patterns_bytes) {
+            if pattern.rows.is_empty() {
+                pattern_offsets.push(0);
+                continue;
+            }
+
+            pattern_offsets.push(offset);
+            offset += 8 + bytes.len() as u32;
+        }
+
+        let mut sample_pointers = Vec::<u32>::with_capacity(self.samples.len());
+        for sample in &self.samples {
+            sample_pointers.push(offset);
+            offset += if sample.flags & 0b10 != 0 {
+                sample.audio.len() as u32 * 2
+            } else {
+                sample.audio.len() as u32
+            };
+        }
+
+        // --- HEADER START ---
+        // 0000
+        writer.write_all(b"IMPM")?;
+        writer.write_all(&self.song_name)?;
+
+        // 001E
+        writer.write_u16::<LittleEndian>(self._pattern_highlight)?;
+
+        // 0020
+        writer.write_u16::<LittleEndian>(self.orders.len() as u16)?;
+        writer.write_u16::<LittleEndian>(self.instruments.len() as u16)?;
+        writer.write_u16::<LittleEndian>(self.samples.len() as u16)?;
+        writer.write_u16::<LittleEndian>(self.patterns.len() as u16)?;
+        writer.write_u16::<LittleEndian>(self.tracker_id)?;
+        writer.write_u16::<LittleEndian>(self.format_id)?;
+        writer.write_u16::<LittleEndian>(self.flags)?;
+        writer.write_u16::<LittleEndian>(self.special & !0b1011)?; // No message, edit history or MIDI config
+
+        // 0030
+        writer.write_u8(self.global_volume)?;
+        writer.write_u8(self.mixing_volume)?;
+        writer.write_u8(self.initial_speed)?;
+        writer.write_u8(self.initial_tempo)?;
+        writer.write_u8(self.separation)?;
+        writer.write_u8(self.pitch_wheel_depth)?;
+        writer.write_u16::<LittleEndian>(0)?; // Message length
+        writer.write_u32::<LittleEndian>(0)?; // Message offset
+        writer.write_all(&[0; 4])?;
+
+        // 0040
+        writer.write_all(&self.channel_pan)?;
+
+        // 0080
+        writer.write_all(&self.channel_volume)?;
+
+        // 00C0
+        writer.write_all(&self.orders)?;
+
+        // xxxx (Offsets)
+        for offset in instrument_offsets
+            .iter()
+            .chain(&sample_offsets)
+            .chain(&pattern_offsets)
+        {
+            writer.write_u32::<LittleEndian>(*offset)?;
+        }
+        // --- HEADER END ---
+
+        // --- INSTRUMENTS START ---
+        for instrument in &self.instruments {
+            // 0000
+            writer.write_all(b"IMPI")?;
+            writer.write_all(&instrument.filename)?;
+
+            // 0010
+            writer.write_u8(instrument._00h)?;
+            writer.write_u8(instrument.new_note_action)?;
+            writer.write_u8(instrument.duplicate_check_type)?;
+            writer.write_u8(instrument.duplicate_check_action)?;
+            writer.write_u16::<LittleEndian>(instrument.fadeout)?;
+            writer.write_i8(instrument.pitch_pan_sepraration)?;
+            writer.write_u8(instrument.pitch_pan_center)?;
+            writer.write_u8(instrument.global_volume)?;
+            writer.write_u8(instrument.default_pan)?;
+            writer.write_u8(instrument.random_volume)?;
+            writer.write_u8(instrument.random_pan)?;
+            writer.write_u16::<LittleEndian>(instrument._tracker_version)?;
+            writer.write_u8(instrument._number_of_samples)?;
+            writer.write_u8(instrument._x)?;
+
+            // 0020
+            writer.write_all(&instrument.instrument_name)?;
+
+            // 0030
+            writer.write_u8(instrument.initial_filter_cutoff)?;
+            writer.write_u8(instrument.initial_filter_resonance)?;
+            writer.write_u8(instrument.midi_channel)?;
+            writer.write_u8(instrument.midi_program)?;
+            writer.write_u16::<LittleEndian>(instrument.midi_bank)?;
+
+            // 0040
+            for pair in &instrument.note_sample_table {
+                writer.write_u8(pair.note)?;
+                writer.write_u8(pair.sample)?;
+            }
+
+            // 0130, 0182, 01D4
+            for env in &instrument.envelopes {
+                writer.write_u8(env.flag)?;
+                writer.write_u8(env.node_amount)?;
+                writer.write_u8(env.loop_begin)?;
+                writer.write_u8(env.loop_end)?;
+                writer.write_u8(env.sustain_loop_begin)?;
+                writer.write_u8(env.sustain_loop_end)?;
+
+                for i in 0..25 {
+                    match env.nodes.get(i) {
+                        Some(node) => {
+                            writer.write_u8(node.y)?;
+                            writer.write_u16::<LittleEndian>(node.tick)?;
+                        }
+                        None => writer.write_all(&[0; 3])?,
+                    }
+                }
+                writer.write_u8(0)?; // padding
+            }
+
+            writer.write_all(&[0; 4])?; // pad to 554 bytes
+        }
+        // --- INSTRUMENTS END ---
+
+        // --- SAMPLES START ---
+        for (sample, pointer) in self.samples.iter().zip(&sample_pointers) {
+            // 0000
+            writer.write_all(b"IMPS")?;
+            writer.write_all(&sample.filename)?;
+
+            // 0010
+            writer.write_u8(sample._00h)?;
+            writer.write_u8(sample.global_volume)?;
+            writer.write_u8(sample.flags)?;
+            writer.write_u8(sample.volume)?;
+            writer.write_all(&sample.sample_name)?;
+
+            // 0020
+            writer.write_u8(sample.convert)?;
+            writer.write_u8(sample.default_pan)?;
+
+            // 0030
+            writer.write_u32::<LittleEndian>(sample.audio.len() as u32)?;
+            writer.write_u32::<LittleEndian>(sample.loop_begin)?;
+            writer.write_u32::<LittleEndian>(sample.loop_end)?;
+            writer.write_u32::<LittleEndian>(sample.c5_speed)?;
+
+            // 0040
+            writer.write_u32::<LittleEndian>(sample.sustain_loop_begin)?;
+            writer.write_u32::<LittleEndian>(sample.sustain_loop_end)?;
+            writer.write_u32::<LittleEndian>(*pointer)?;
+
+            writer.write_u8(sample.vibrato_speed)?;
+            writer.write_u8(sample.vibrato_depth)?;
+            writer.write_u8(sample.vibrato_rate)?;
+            writer.write_u8(sample.vibrato_type)?;
+        }
+        // --- SAMPLES END ---
+
+        // --- PATTERNS START ---
+        for (pattern, bytes) in self.patterns.iter().zip(&patterns_bytes) {
+            if pattern.rows.is_empty() {
+                continue;
+            }
+
+            writer.write_u16::<LittleEndian>(bytes.len() as u16)?;
+            writer.write_u16::<LittleEndian>(pattern.rows.len() as u16)?;
+            writer.write_all(&pattern._x)?;
+            writer.write_all(bytes)?;
+        }
+        // --- PATTERNS END ---
+
+        // Data
+        for sample in &self.samples {
+            if sample.flags & 0b10 != 0 {
+                // Sample is 16 bit, unsigned samples were read with an `as i16` cast
+                for x in &sample.audio {
+                    writer.write_i16::<LittleEndian>(*x)?;
+                }
+            } else {
+                // Sample is 8 bit, undo the *128 from loading
+                for x in &sample.audio {
+                    if sample.convert & 0b1 != 0 {
+                        writer.write_i8((*x / 128) as i8)?;
+                    } else {
+                        writer.write_u8((*x / 128) as u8)?;
+                    }
+                }
+            }
+        }
+
+        writer.flush()
+    }
+}
+
+impl ITModule {
+    // Writes what a Module can change back into the file's own structures, so save
+    // keeps edits made after loading. Everything a Module doesn't know about (names,
+    // pan and pitch envelopes, vibrato...) is left as loaded
+    pub fn apply(&mut self, module: &Module) {
+        // Names are decoded lossily, only an actual change is written back
+        let name = String::from_utf8_lossy(&self.song_name);
+        if name.trim_end_matches("\0") != module.name {
+            self.song_name = [0; 26];
+            let bytes = &module.name.as_bytes()[..module.name.len().min(25)];
+            self.song_name[..bytes.len()].copy_from_slice(bytes);
+        }
+
+        self.flags &= !0b101100;
+        if matches!(module.mode, PlaybackMode::IT) {
+            self.flags |= 0b100; // Bit 2: Use instruments
+        }
+        if module.linear_freq_slides {
+            self.flags |= 0b1000; // Bit 3: Linear slides
+        }
+        if module.compatible_gxx {
+            self.flags |= 0b100000; // Bit 5: Compatible Gxx
+        }
+        self.initial_tempo = module.initial_tempo;
+        self.initial_speed = module.initial_speed;
+        self.global_volume = module.initial_global_volume;
+        for (i, pan) in self.channel_pan.iter_mut().enumerate() {
+            *pan = module.initial_panning[i] | if module.channel_enabled[i] { 0 } else { 128 };
+        }
+        self.orders = module.playlist.clone();
+
+        self.samples.resize_with(module.samples.len(), || ITSample {
+            flags: 0b1,   // Has data
+            convert: 0b1, // Signed
+            ..Default::default()
+        });
+        for (it, s) in self.samples.iter_mut().zip(&module.samples) {
+            it.c5_speed = s.base_frequency;
+            it.flags &= !0b01010000;
+            it.flags |= match s.loop_type {
+                LoopType::None => 0,
+                LoopType::Forward => 16,
+                LoopType::PingPong => 80,
+            };
+            it.loop_begin = s.loop_start;
+            it.loop_end = s.loop_end;
+            it.volume = s.default_volume;
+            it.global_volume = s.global_volume;
+
+            if it.audio != s.audio {
+                it.audio = s.audio.clone();
+                it.length = it.audio.len() as u32;
+                // New audio is plain signed PCM. 8 bit can only hold it if it came from 8 bit
+                it.flags = (it.flags | 0b1) & !0b1000;
+                it.convert |= 0b1;
+                if it.audio.iter().any(|x| x % 128 != 0) {
+                    it.flags |= 0b10;
+                }
+            }
+        }
+
+        self.instruments
+            .resize_with(module.instruments.len(), ITInstrument::default);
+        for (it, i) in self.instruments.iter_mut().zip(&module.instruments) {
+            it.fadeout = i.fadeout;
+            it.new_note_action = match i.new_note_action {
+                NewNoteAction::Cut => 0,
+                NewNoteAction::Continue => 1,
+                NewNoteAction::Off => 2,
+                NewNoteAction::Fade => 3,
+            };
+            it.duplicate_check_type = match i.duplicate_check {
+                DuplicateCheck::None => 0,
+                DuplicateCheck::Note => 1,
+                DuplicateCheck::Sample => 2,
+                DuplicateCheck::Instrument => 3,
+            };
+            it.duplicate_check_action = match i.duplicate_check_action {
+                NewNoteAction::Off => 1,
+                NewNoteAction::Fade => 2,
+                _ => 0,
+            };
+            it.note_sample_table = (0..120)
+                .map(|n| {
+                    let (note, sample) = i.sample_map.get(n).copied().unwrap_or((n as u8, 0));
+                    ITNoteSamplePair { note, sample }
+                })
+                .collect();
+
+            let envelope = &i.volume_envelope;
+            let it_envelope = &mut it.envelopes[0];
+            it_envelope.flag &= !0b1111;
+            it_envelope.flag |= envelope.enabled as u8
+                | (envelope.loop_enabled as u8) << 1
+                | (envelope.sustain_enabled as u8) << 2
+                | (envelope.carry as u8) << 3;
+            it_envelope.loop_begin = envelope.loop_start;
+            it_envelope.loop_end = envelope.loop_end;
+            it_envelope.sustain_loop_begin = envelope.sustain_start;
+            it_envelope.sustain_loop_end = envelope.sustain_end;
+            it_envelope.nodes = envelope
+                .nodes
+                .iter()
+                .take(25)
+                .map(|&(tick, y)| ITEnvelopeNode { y, tick })
+                .collect();
+            it_envelope.node_amount = it_envelope.nodes.len() as u8;
+        }
+
+        self.patterns = module
+            .patterns
+            .iter()
+            .map(|p| ITPattern {
+                rows_amount: p.len() as u16,
+                rows: p
+                    .iter()
+                    .map(|r| r.iter().map(it_column).collect())
+                    .collect(),
+                ..Default::default()
+            })
+            .collect();
+    }
+}
+
+// The reverse of ModuleInterface::patterns for one column
+fn it_column(c: &Column) -> ITColumn {
+    let (effect, effect_value) = match c.effect {
+        Effect::None => (0, 0),
+        Effect::SetSpeed(x) => (1, x),
+        Effect::PosJump(x) => (2, x),
+        Effect::PatBreak(x) => (3, x),
+        Effect::VolSlide(x) => (4, x),
+        Effect::PortaDown(x) => (5, x),
+        Effect::PortaUp(x) => (6, x),
+        Effect::TonePorta(x) => (7, x),
+        Effect::Vibrato(x) => (8, x),
+        Effect::Tremor(x) => (9, x),
+        Effect::Arpeggio(x) => (10, x),
+        Effect::VolSlideVibrato(x) => (11, x),
+        Effect::VolSlideTonePorta(x) => (12, x),
+        Effect::SetChanVol(x) => (13, x),
+        Effect::ChanVolSlide(x) => (14, x),
+        Effect::SampleOffset(x) => (15, x),
+        Effect::PanSlide(x) => (16, x),
+        Effect::Retrig(x) => (17, x),
+        Effect::Tremolo(x) => (18, x),
+
+        // Sxy
+        Effect::GlissandoControl(on) => (19, 0x10 | on as u8),
+        Effect::SetFinetune(x) => (19, 0x20 | x),
+        Effect::SetVibratoWaveform(x) => (19, 0x30 | x),
+        Effect::SetTremoloWaveform(x) => (19, 0x40 | x),
+        Effect::SetPanbrelloWaveform(x) => (19, 0x50 | x),
+        Effect::FinePatternDelay(x) => (19, 0x60 | x),
+        Effect::PastNoteCut => (19, 0x70),
+        Effect::PastNoteOff => (19, 0x71),
+        Effect::PastNoteFade => (19, 0x72),
+        Effect::NNANoteCut => (19, 0x73),
+        Effect::NNANoteContinue => (19, 0x74),
+        Effect::NNANoteOff => (19, 0x75),
+        Effect::NNANoteFade => (19, 0x76),
+        Effect::VolEnvOff => (19, 0x77),
+        Effect::VolEnvOn => (19, 0x78),
+        Effect::PanEnvOff => (19, 0x79),
+        Effect::PanEnvOn => (19, 0x7A),
+        Effect::PitchEnvOff => (19, 0x7B),
+        Effect::PitchEnvOn => (19, 0x7C),
+        Effect::SetPan(x) => (19, 0x80 | x),
+        Effect::SoundControl(x) => (19, 0x90 | x),
+        Effect::HighOffset(x) => (19, 0xA0 | x),
+        Effect::PatLoopStart => (19, 0xB0),
+        Effect::PatLoop(x) => (19, 0xB0 | x),
+        Effect::NoteCut(x) => (19, 0xC0 | x),
+        Effect::NoteDelay(x) => (19, 0xD0 | x),
+        Effect::PatDelay(x) => (19, 0xE0 | x),
+        Effect::SetActiveMacro(x) => (19, 0xF0 | x),
+
+        Effect::DecTempo(x) => (20, x),
+        Effect::IncTempo(x) => (20, 0x10 | x),
+        Effect::SetTempo(x) => (20, x),
+        Effect::FineVibrato(x) => (21, x),
+        Effect::SetGlobalVol(x) => (22, x),
+        Effect::GlobalVolSlide(x) => (23, x),
+        Effect::FineSetPan(x) => (24, x),
+        Effect::Panbrello(x) => (25, x),
+        Effect::MIDIMacro(x) => (26, x),
+    };
+
+    ITColumn {
+        note: match c.note {
+            Note::None => 120,
+            Note::Fade => 246,
+            Note::Cut => 254,
+            Note::Off => 255,
+            Note::On(note) => note,
+        },
+        instrument: c.instrument,
+        vol: match c.vol {
+            VolEffect::None => 255,
+            VolEffect::Volume(x) => x,
+            VolEffect::FineVolSlideUp(x) => 65 + x,
+            VolEffect::FineVolSlideDown(x) => 75 + x,
+            VolEffect::VolSlideUp(x) => 85 + x,
+            VolEffect::VolSlideDown(x) => 95 + x,
+            VolEffect::PortaDown(x) => 105 + x,
+            VolEffect::PortaUp(x) => 115 + x,
+            VolEffect::SetPan(x) => 128 + x,
+            VolEffect::TonePorta(x) => 193 + x,
+            VolEffect::VibratoDepth(x) => 203 + x,
+        },
+        effect,
+        effect_value,
+    }
+}
+
+impl ITModule {
+    pub fn tracker_name(&self) -> String {
+        // Cwt/v, upper nibble identifies the tracker for everything but IT itself
+        let version = self.tracker_id & 0x0FFF;
+        match self.tracker_id >> 12 {
+            // Also written by OpenMPT before it had an ID of its own
+            0x0 if self.tracker_id == 0x0888 => "ModPlug Tracker or early OpenMPT".to_string(),
+            0x0 => format!("Impulse Tracker {}.{:02X}", version >> 8, version & 0xFF),
+            0x1 => "Schism Tracker".to_string(),
+            0x5 => format!("OpenMPT {}.{:02X}", version >> 8, version & 0xFF),
+            0x6 => "BeRoTracker".to_string(),
+            0x7 => "ITMCK".to_string(),
+            0x8 => "Tralala".to_string(),
+            0xC => "ChickDune ChipTune Tracker".to_string(),
+            _ => "Unknown".to_string(),
+        }
+    }
+}
+
 impl ModuleInterface for ITModule {
     fn samples(&self) -> Vec<Sample> {
         self.samples
@@ -637,21 +1166,75 @@ impl ModuleInterface for ITModule {
             .map(|s| Sample {
                 base_frequency: s.c5_speed,
                 loop_type: match s.flags & 0b01010000 {
+                    // Zero-length or inverted loop
+                    _ if s.loop_end.min(s.audio.len() as u32) <= s.loop_begin => LoopType::None,
                     16 => LoopType::Forward,
                     80 => LoopType::PingPong,
                     _ => LoopType::None,
                 },
                 loop_start: s.loop_begin,
-                loop_end: s.loop_end,
+                loop_end: s.loop_end.min(s.audio.len() as u32),
 
-                default_volume: s.volume,
-                global_volume: s.global_volume,
+                // Both are 0..=64 in IT, anything above is out of spec
+                default_volume: s.volume.min(64),
+                global_volume: s.global_volume.min(64),
 
                 audio: s.audio.clone(),
             })
             .collect()
     }
 
+    fn instruments(&self) -> Vec<Instrument> {
+        self.instruments
+            .iter()
+            .map(|i| {
+                let envelope = &i.envelopes[0]; // Volume, then panning and pitch
+
+                Instrument {
+                    fadeout: i.fadeout,
+                    new_note_action: match i.new_note_action {
+                        1 => NewNoteAction::Continue,
+                        2 => NewNoteAction::Off,
+                        3 => NewNoteAction::Fade,
+                        _ => NewNoteAction::Cut,
+                    },
+                    duplicate_check: match i.duplicate_check_type {
+                        1 => DuplicateCheck::Note,
+                        2 => DuplicateCheck::Sample,
+                        3 => DuplicateCheck::Instrument,
+                        _ => DuplicateCheck::None,
+                    },
+                    duplicate_check_action: match i.duplicate_check_action {
+                        1 => NewNoteAction::Off,
+                        2 => NewNoteAction::Fade,
+                        _ => NewNoteAction::Cut,
+                    },
+                    sample_map: i
+                        .note_sample_table
+                        .iter()
+                        .map(|p| (p.note, p.sample))
+                        .collect(),
+
+                    volume_envelope: Envelope {
+                        enabled: envelope.flag & 0b001 != 0,         // Bit 0: On
+                        loop_enabled: envelope.flag & 0b010 != 0,    // Bit 1: Loop
+                        sustain_enabled: envelope.flag & 0b100 != 0, // Bit 2: Sustain loop
+                        carry: envelope.flag & 0b1000 != 0,          // Bit 3: Carry
+                        loop_start: envelope.loop_begin,
+                        loop_end: envelope.loop_end,
+                        sustain_start: envelope.sustain_loop_begin,
+                        sustain_end: envelope.sustain_loop_end,
+                        nodes: envelope
+                            .nodes
+                            .iter()
+                            .map(|n| (n.tick, n.y.min(64)))
+                            .collect(),
+                    },
+                }
+            })
+            .collect()
+    }
+
     fn patterns(&self) -> Vec<Pattern> {
         let mut patterns = Vec::<Pattern>::with_capacity(self.patterns.len());
 
@@ -774,10 +1357,16 @@ impl ModuleInterface for ITModule {
             } else {
                 PlaybackMode::ITSample
             },
+            tracker: self.tracker_name(),
             linear_freq_slides: self.flags & 0b1000 != 0, // Bit 3: On = Linear slides, Off = Amiga slides.
+            compatible_gxx: self.flags & 0b100000 != 0,   // Bit 5: Compatible Gxx
             initial_tempo: self.initial_tempo,
             initial_speed: self.initial_speed,
+            initial_global_volume: self.global_volume,
+            channel_enabled: self.channel_pan.map(|p| p & 128 == 0), // Bit 7: channel disabled
+            initial_panning: self.channel_pan.map(|p| p & 127),
             samples: self.samples(),
+            instruments: self.instruments(),
             patterns: self.patterns(),
             playlist: self.orders.clone(),
             name: String::from_utf8_lossy(&self.song_name)
@@ -786,3 +1375,196 @@ impl ModuleInterface for ITModule {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn column(note: u8, instrument: u8, vol: u8, effect: u8, effect_value: u8) -> ITColumn {
+        ITColumn {
+            note,
+            instrument,
+            vol,
+            effect,
+            effect_value,
+        }
+    }
+
+    fn test_module() -> ITModule {
+        let mut module = ITModule::default();
+        module.song_name[..4].copy_from_slice(b"Test");
+        module.flags = 0b1101; // Stereo, instruments, linear slides
+        module.global_volume = 128;
+        module.mixing_volume = 48;
+        module.initial_speed = 6;
+        module.initial_tempo = 125;
+        module.channel_pan = [32; 64];
+        module.channel_volume = [64; 64];
+        module.orders = vec![0, 255];
+
+        let instrument = ITInstrument {
+            fadeout: 256,
+            new_note_action: 2,
+            note_sample_table: (0..120)
+                .map(|note| ITNoteSamplePair { note, sample: 1 })
+                .collect(),
+            envelopes: [
+                ITEnvelope {
+                    flag: 0b101,
+                    node_amount: 3,
+                    sustain_loop_begin: 1,
+                    sustain_loop_end: 1,
+                    nodes: vec![
+                        ITEnvelopeNode { y: 64, tick: 0 },
+                        ITEnvelopeNode { y: 32, tick: 10 },
+                        ITEnvelopeNode { y: 0, tick: 20 },
+                    ],
+                    ..Default::default()
+                },
+                ITEnvelope::default(),
+                ITEnvelope::default(),
+            ],
+            ..Default::default()
+        };
+        module.instruments.push(instrument);
+
+        module.samples.push(ITSample {
+            global_volume: 64,
+            flags: 0b10011, // Has data, 16 bit, loop
+            volume: 64,
+            convert: 1,
+            loop_begin: 2,
+            loop_end: 6,
+            c5_speed: 8363,
+            audio: vec![0, 1000, -1000, 32767, -32768, 5, -5, 0],
+            ..Default::default()
+        });
+        module.samples.push(ITSample {
+            global_volume: 48,
+            flags: 0b1, // Has data, 8 bit
+            volume: 32,
+            convert: 1,
+            c5_speed: 22050,
+            audio: vec![0, 128, -128, 127 * 128, -128 * 128],
+            ..Default::default()
+        });
+
+        module.patterns.push(ITPattern {
+            rows: vec![
+                vec![column(60, 1, 64, 1, 3), column(120, 0, 255, 19, 0xB0)],
+                vec![column(120, 0, 255, 0, 0), column(254, 0, 255, 0, 0)],
+                vec![column(72, 2, 130, 8, 0x44), column(120, 0, 255, 19, 0xB3)],
+                vec![column(255, 0, 75, 0, 0), column(120, 0, 255, 0, 0)],
+            ],
+            ..Default::default()
+        });
+
+        module
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let module = test_module();
+
+        let mut saved = Vec::<u8>::new();
+        module.save(&mut saved).unwrap();
+        let reloaded = ITModule::load(Cursor::new(&saved)).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(
+            format!("{:?}", module.module()),
+            format!("{:?}", reloaded.module())
+        );
+
+        // Saving again gives the same bytes
+        let mut saved_again = Vec::<u8>::new();
+        reloaded.save(&mut saved_again).unwrap();
+        assert_eq!(saved, saved_again);
+    }
+
+    #[test]
+    fn save_rejects_compressed_samples() {
+        let mut module = test_module();
+        module.samples[0].flags |= 0b1000;
+
+        assert!(module.save(&mut Vec::<u8>::new()).is_err());
+    }
+
+    #[test]
+    fn save_rejects_oversized_patterns() {
+        let mut module = test_module();
+        // 7 bytes per column plus the row ends, well past 65535
+        module.patterns[0].rows = (0..200)
+            .map(|_| (0..64).map(|_| column(60, 1, 64, 1, 3)).collect())
+            .collect();
+
+        assert!(module.save(&mut Vec::<u8>::new()).is_err());
+    }
+
+    #[test]
+    fn apply_unchanged_module() {
+        let mut module = test_module();
+        let mut saved = Vec::<u8>::new();
+        module.save(&mut saved).unwrap();
+
+        module.apply(&module.module());
+        let mut applied = Vec::<u8>::new();
+        module.save(&mut applied).unwrap();
+        assert_eq!(saved, applied);
+    }
+
+    #[test]
+    fn apply_keeps_edits() {
+        let mut module = test_module();
+        let mut edited = module.module();
+        edited.name = "Edited".to_string();
+        edited.initial_tempo = 150;
+        edited.channel_enabled[1] = false;
+        edited.instruments[0].new_note_action = NewNoteAction::Fade;
+        edited.instruments[0].volume_envelope.nodes[1] = (12, 40);
+        edited.instruments[0].volume_envelope.carry = true;
+        edited.patterns[0][1][0].note = Note::On(64);
+        edited.patterns[0][1][0].effect = Effect::NNANoteContinue;
+        edited.patterns[0][1][1].vol = VolEffect::VolSlideDown(3);
+        // Doesn't fit in 8 bits anymore
+        edited.samples[1].audio = vec![1, -2, 3];
+
+        module.apply(&edited);
+        let mut saved = Vec::<u8>::new();
+        module.save(&mut saved).unwrap();
+        let reloaded = ITModule::load(Cursor::new(&saved)).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(format!("{:?}", reloaded.module()), format!("{:?}", edited));
+    }
+
+    #[test]
+    fn degenerate_loops_load_unlooped() {
+        let mut module = test_module();
+        // Zero-length, then only past the end of the audio
+        module.samples[0].loop_begin = 4;
+        module.samples[0].loop_end = 4;
+        module.samples[1].flags |= 0b10000;
+        module.samples[1].loop_begin = 5;
+        module.samples[1].loop_end = 10;
+
+        let samples = module.samples();
+        assert!(matches!(samples[0].loop_type, LoopType::None));
+        assert!(matches!(samples[1].loop_type, LoopType::None));
+    }
+
+    #[test]
+    fn tracker_names() {
+        let name = |tracker_id| {
+            ITModule {
+                tracker_id,
+                ..Default::default()
+            }
+            .tracker_name()
+        };
+        assert_eq!(name(0x0214), "Impulse Tracker 2.14");
+        assert_eq!(name(0x0888), "ModPlug Tracker or early OpenMPT");
+        assert_eq!(name(0x1050), "Schism Tracker");
+        assert_eq!(name(0x5117), "OpenMPT 1.17");
+        assert_eq!(name(0xF000), "Unknown");
+    }
+}
@@ -0,0 +1,82 @@
+use std::{f32::consts::PI, sync::OnceLock};
+
+// Sub-sample positions the windowed sinc is precomputed at
+pub const SINC_PHASES: usize = 256;
+
+fn sinc(x: f32) -> f32 {
+    if (-0.0001..=0.0001).contains(&x) {
+        return 1.0;
+    };
+    (x * PI).sin() / (x * PI)
+}
+
+// Blackman window, x in -1..=1
+fn blackman(x: f32) -> f32 {
+    let x = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * x).cos() + 0.08 * (4.0 * PI * x).cos()
+}
+
+fn build_sinc_table(half_width: usize) -> Vec<f32> {
+    let taps = half_width * 2;
+    let mut table = Vec::<f32>::with_capacity((SINC_PHASES + 1) * taps);
+
+    for phase in 0..=SINC_PHASES {
+        let fraction = phase as f32 / SINC_PHASES as f32;
+        let start = table.len();
+
+        for i in 0..taps {
+            let x = i as f32 - (half_width as f32 - 1.0) - fraction;
+            table.push(sinc(x) * blackman(x / half_width as f32));
+        }
+
+        // Normalize so every phase has unity gain at DC
+        let sum: f32 = table[start..].iter().sum();
+        for c in table[start..].iter_mut() {
+            *c /= sum;
+        }
+    }
+
+    table
+}
+
+// Windowed sinc coefficients for the taps at offsets 1-half_width..=half_width
+// around a sample position, stored phase after phase (SINC_PHASES + 1 of them)
+pub fn sinc_table(half_width: usize) -> &'static [f32] {
+    static SINC16: OnceLock<Vec<f32>> = OnceLock::new();
+    static SINC32: OnceLock<Vec<f32>> = OnceLock::new();
+    static SINC64: OnceLock<Vec<f32>> = OnceLock::new();
+
+    match half_width {
+        16 => SINC16.get_or_init(|| build_sinc_table(16)),
+        32 => SINC32.get_or_init(|| build_sinc_table(32)),
+        64 => SINC64.get_or_init(|| build_sinc_table(64)),
+        _ => unreachable!(),
+    }
+}
+
+// One cycle of a sine, 64 steps from -64 to 64
+const SINE: [i8; 64] = [
+    0, 6, 12, 19, 24, 30, 36, 41, 45, 49, 53, 56, 59, 61, 63, 64, 64, 64, 63, 61, 59, 56, 53, 49,
+    45, 41, 36, 30, 24, 19, 12, 6, 0, -6, -12, -19, -24, -30, -36, -41, -45, -49, -53, -56, -59,
+    -61, -63, -64, -64, -64, -63, -61, -59, -56, -53, -49, -45, -41, -36, -30, -24, -19, -12, -6,
+];
+
+// Vibrato/tremolo/panbrello oscillator at `position` (64 steps per cycle), waveforms
+// numbered like S3x: 0 = sine, 1 = ramp down, 2 = square, 3 = random
+pub fn oscillator(waveform: u8, position: u8) -> i8 {
+    let position = position & 63;
+
+    match waveform & 3 {
+        0 => SINE[position as usize],
+        1 => 64 - (position * 2) as i8,
+        2 => {
+            if position < 32 {
+                64
+            } else {
+                -64
+            }
+        }
+        // Random has no fixed shape, Oscillator draws it from its own generator
+        _ => 0,
+    }
+}
@@ -1,3 +1,4 @@
 pub mod format_it;
+pub mod lut;
 pub mod module;
 pub mod player;
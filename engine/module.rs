@@ -10,6 +10,42 @@ pub enum Note {
     Off,
 }
 
+#[derive(Default, Debug, Clone, Copy)]
+pub struct NoteFormat {
+    pub flats: bool,       // Db instead of C#
+    pub octave_offset: i8, // added to note/12, -1 gives C-4 as middle C
+}
+
+impl NoteFormat {
+    pub fn format(&self, note: Note) -> String {
+        let key = match note {
+            Note::None => return "...".to_string(),
+            Note::Fade => return "Fde".to_string(),
+            Note::Cut => return "Cut".to_string(),
+            Note::Off => return "Off".to_string(),
+            Note::On(key) => key,
+        };
+
+        let name = if self.flats {
+            [
+                "C-", "Db", "D-", "Eb", "E-", "F-", "Gb", "G-", "Ab", "A-", "Bb", "B-",
+            ]
+        } else {
+            [
+                "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
+            ]
+        }[(key % 12) as usize];
+
+        format!("{}{}", name, (key / 12) as i16 + self.octave_offset as i16)
+    }
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", NoteFormat::default().format(*self))
+    }
+}
+
 #[derive(Default, Debug)]
 pub enum Effect {
     // Based off IT's set
@@ -127,6 +163,79 @@ pub struct Sample {
     pub audio: Vec<i16>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Envelope {
+    pub enabled: bool,
+    pub loop_enabled: bool,
+    pub sustain_enabled: bool,
+    pub carry: bool, // A new note of the same instrument picks up where the last one was
+
+    // Node indices
+    pub loop_start: u8,
+    pub loop_end: u8,
+    pub sustain_start: u8,
+    pub sustain_end: u8,
+
+    pub nodes: Vec<(u16, u8)>, // tick, value (0..=64 for volume)
+}
+
+impl Envelope {
+    // Value at `tick`, linearly interpolated between nodes and held after the last one
+    pub fn value(&self, tick: u16) -> f32 {
+        let Some(&(mut previous)) = self.nodes.first() else {
+            return 64.0;
+        };
+
+        for &(node_tick, value) in &self.nodes[1..] {
+            if tick < node_tick {
+                let t = tick.saturating_sub(previous.0) as f32
+                    / node_tick.saturating_sub(previous.0).max(1) as f32;
+                return previous.1 as f32 + (value as f32 - previous.1 as f32) * t;
+            }
+            previous = (node_tick, value);
+        }
+
+        previous.1 as f32
+    }
+
+    // Tick of a node, out of range indices give the last node
+    pub fn node_tick(&self, node: u8) -> u16 {
+        self.nodes
+            .get(node as usize)
+            .or(self.nodes.last())
+            .map_or(0, |n| n.0)
+    }
+}
+
+// What happens to a note when another one starts on its channel
+#[derive(Debug, Clone, Copy)]
+pub enum NewNoteAction {
+    Cut,
+    Continue,
+    Off,
+    Fade,
+}
+
+// Which of a channel's older notes count as the same as a new one
+#[derive(Debug, Clone, Copy)]
+pub enum DuplicateCheck {
+    None,
+    Note,
+    Sample,
+    Instrument,
+}
+
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub fadeout: u16, // Taken off the 1024 fade volume every tick once the note fades
+    pub new_note_action: NewNoteAction,
+    pub duplicate_check: DuplicateCheck,
+    pub duplicate_check_action: NewNoteAction, // Never Continue
+    pub sample_map: Vec<(u8, u8)>, // For each of the 120 notes: note to play, sample (0 = none)
+
+    pub volume_envelope: Envelope,
+}
+
 pub type Pattern = Vec<Row>;
 pub type Row = Vec<Column>;
 
@@ -142,21 +251,170 @@ pub struct Column {
 pub struct Module {
     pub name: String,
     pub mode: PlaybackMode,
+    pub tracker: String, // Tracker that saved the file, if the header says
 
     pub linear_freq_slides: bool,
+    pub compatible_gxx: bool, // IT: Gxx keeps its own memory instead of sharing Exx/Fxx's
     pub initial_tempo: u8,
     pub initial_speed: u8,
+    pub initial_global_volume: u8,
+    pub channel_enabled: [bool; 64], // Disabled channels are silent, only their effects run
+    pub initial_panning: [u8; 64],   // 0..=64, 100 = surround
 
     pub samples: Vec<Sample>,
-    //TODO: instruments
+    pub instruments: Vec<Instrument>, // Only used in PlaybackMode::IT
     pub patterns: Vec<Pattern>,
     pub playlist: Vec<u8>,
 }
 
+impl Module {
+    pub fn format(&self) -> (&PlaybackMode, &str) {
+        (&self.mode, &self.tracker)
+    }
+
+    // Swaps in new audio for a sample (e.g. from an external WAV) recorded at `rate` Hz.
+    // With `resample` the audio is converted to the sample's own base frequency, so its
+    // tuning stays as it was. Otherwise the base frequency becomes `rate`, which plays
+    // the audio at its original pitch on C-5. Without a rate the audio is taken as is.
+    // Loop points are kept but clamped to the new length, the returned sample can be
+    // used to adjust them further. Players borrow the module, so one made before has
+    // to be dropped and built again with Player::from_module to hear the new audio
+    pub fn replace_sample(
+        &mut self,
+        index: usize,
+        audio: Vec<i16>,
+        rate: Option<u32>,
+        resample: bool,
+    ) -> Option<&mut Sample> {
+        let sample = self.samples.get_mut(index)?;
+
+        sample.audio = match rate {
+            Some(rate) if resample => resample_linear(&audio, rate, sample.base_frequency),
+            _ => audio,
+        };
+        if let (Some(rate), false) = (rate, resample) {
+            sample.base_frequency = rate;
+        }
+
+        let length = sample.audio.len() as u32;
+        sample.loop_end = sample.loop_end.min(length);
+        sample.loop_start = sample.loop_start.min(sample.loop_end);
+        if sample.loop_start == sample.loop_end {
+            sample.loop_type = LoopType::None;
+        }
+
+        Some(sample)
+    }
+}
+
+// Linear interpolation from one sample rate to another, plenty for fitting imported PCM
+fn resample_linear(audio: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if audio.is_empty() || from == 0 || to == 0 || from == to {
+        return audio.to_vec();
+    }
+
+    let length = (audio.len() as u64 * to as u64 / from as u64).max(1) as usize;
+    let step = from as f64 / to as f64;
+    let at = |i: usize| audio[i.min(audio.len() - 1)] as f64;
+
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let fraction = position - index as f64;
+            (at(index) + (at(index + 1) - at(index)) * fraction).round() as i16
+        })
+        .collect()
+}
+
 pub trait ModuleInterface {
     fn samples(&self) -> Vec<Sample>;
-    // TODO: instruments
+    fn instruments(&self) -> Vec<Instrument>;
     fn patterns(&self) -> Vec<Pattern>;
 
     fn module(&self) -> Module;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_module() -> Module {
+        Module {
+            name: String::new(),
+            mode: PlaybackMode::ITSample,
+            tracker: String::new(),
+            linear_freq_slides: true,
+            compatible_gxx: false,
+            initial_tempo: 125,
+            initial_speed: 6,
+            initial_global_volume: 128,
+            channel_enabled: [true; 64],
+            initial_panning: [32; 64],
+            samples: vec![Sample {
+                base_frequency: 8000,
+                loop_type: LoopType::Forward,
+                loop_start: 2,
+                loop_end: 6,
+                default_volume: 64,
+                global_volume: 64,
+                audio: vec![0; 8],
+            }],
+            instruments: vec![],
+            patterns: vec![],
+            playlist: vec![255],
+        }
+    }
+
+    #[test]
+    fn note_format() {
+        let format = |flats, octave_offset| {
+            NoteFormat {
+                flats,
+                octave_offset,
+            }
+            .format(Note::On(61))
+        };
+        assert_eq!(format(false, 0), "C#5");
+        assert_eq!(format(true, 0), "Db5");
+        assert_eq!(format(false, -1), "C#4");
+        assert_eq!(format(true, -1), "Db4");
+        assert_eq!(Note::On(61).to_string(), "C#5");
+    }
+
+    #[test]
+    fn resampling() {
+        assert_eq!(
+            resample_linear(&[0, 100, 200, 300], 2, 4),
+            [0, 50, 100, 150, 200, 250, 300, 300]
+        );
+        assert_eq!(resample_linear(&[0, 100, 200, 300], 4, 2), [0, 200]);
+    }
+
+    #[test]
+    fn replace_sample_fits_rate() {
+        let audio: Vec<i16> = (0..16).map(|x| x * 100).collect();
+
+        // Retuned, the data stays as it is
+        let mut module = test_module();
+        let sample = module
+            .replace_sample(0, audio.clone(), Some(16000), false)
+            .unwrap();
+        assert_eq!((sample.base_frequency, sample.audio.len()), (16000, 16));
+
+        // Resampled to the old rate, half as long at the same pitch and the loop kept
+        let mut module = test_module();
+        let sample = module.replace_sample(0, audio, Some(16000), true).unwrap();
+        assert_eq!(sample.base_frequency, 8000);
+        assert_eq!(sample.audio, [0, 200, 400, 600, 800, 1000, 1200, 1400]);
+        assert!(matches!(sample.loop_type, LoopType::Forward));
+
+        // Too short for the loop
+        let sample = module.replace_sample(0, vec![0; 2], None, false).unwrap();
+        assert!(matches!(sample.loop_type, LoopType::None));
+        assert_eq!(
+            module.replace_sample(1, vec![], None, false).map(|_| ()),
+            None
+        );
+    }
+}
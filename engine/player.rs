@@ -1,12 +1,16 @@
 use std::{
     array,
-    f32::consts::PI,
-    io::{stdout, Write},
+    io::{self, stdout, Seek, SeekFrom, Write},
 };
 
 use crate::engine::module::Effect;
 
-use super::module::{LoopType, Module, Note, VolEffect};
+use super::lut::{oscillator, sinc_table, SINC_PHASES};
+use super::module::{
+    Column, DuplicateCheck, LoopType, Module, NewNoteAction, Note, PlaybackMode, Row, Sample,
+    VolEffect,
+};
+use byteorder::{LittleEndian, WriteBytesExt};
 use sdl2::audio::AudioCallback;
 
 #[derive(Default, Debug, Clone, Copy, clap::ValueEnum)]
@@ -16,7 +20,82 @@ pub enum Interpolation {
     Linear,
     Sinc16,
     Sinc32,
-    // Sinc64
+    Sinc64,
+    Sinc64Fast, // Nearest precomputed phase instead of blending two
+}
+
+// Named presets so users can pick an intent instead of an interpolation mode
+#[derive(Default, Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Quality {
+    Fast,
+    #[default]
+    Balanced,
+    High,
+}
+
+// Live view of a channel after the current tick's effects, for visualizers
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelState {
+    pub playing: bool,
+    pub sample: u8,
+    pub freq: f32,
+    pub volume: f32, // 0..=64, as heard, with every volume and gain on top applied
+    pub pan: u8,     // 0..=64, left to right
+}
+
+// Shared by vibrato, tremolo and panbrello
+#[derive(Clone, Copy, Default)]
+struct Oscillator {
+    waveform: u8,        // See lut::oscillator
+    keep_position: bool, // Set by waveforms 4..=7, the phase carries on across notes
+    position: u8,
+    speed: u8,
+    depth: u8,
+    random: u32, // xorshift state for waveform 3
+}
+
+impl Oscillator {
+    // Oscillators with different seeds give different random waveforms
+    fn new(seed: u32) -> Oscillator {
+        Oscillator {
+            random: seed.wrapping_add(1).wrapping_mul(0x9E3779B9).max(1),
+            ..Default::default()
+        }
+    }
+
+    // S3x/S4x/S5x
+    fn set_waveform(&mut self, value: u8) {
+        self.waveform = value & 3;
+        self.keep_position = value & 4 != 0;
+    }
+
+    // Speed and depth are remembered separately
+    fn set_parameters(&mut self, value: u8, depth_scale: u8) {
+        if value & 0xF0 != 0 {
+            self.speed = (value & 0xF0) >> 4;
+        }
+        if value & 0x0F != 0 {
+            self.depth = (value & 0x0F) * depth_scale;
+        }
+    }
+
+    fn note_on(&mut self) {
+        if !self.keep_position {
+            self.position = 0;
+        }
+    }
+
+    // Current value times depth, then moves on by speed. The random waveform takes a
+    // new value in the same -64..=64 range every step
+    fn step(&mut self) -> f32 {
+        let value = if self.waveform == 3 {
+            (xorshift(&mut self.random) % 129) as f32 - 64.0
+        } else {
+            oscillator(self.waveform, self.position) as f32
+        } * self.depth as f32;
+        self.position = self.position.wrapping_add(self.speed) & 63;
+        value
+    }
 }
 
 #[derive(Clone)]
@@ -24,64 +103,234 @@ struct Channel<'a> {
     module: &'a Module,
 
     current_sample_index: u8,
+    current_instrument: Option<u8>, // Instrument mode only
     playing: bool,
     freq: f32,
     position: f64,
     backwards: bool,
 
-    porta_memory: u8,    // Exx, Fxx, Gxx
-    last_note: u8,       // Gxx
-    offset_memory: u8,   // Oxx
-    volume_memory: u8,   // Dxy
-    retrigger_ticks: u8, // Qxy
+    porta_memory: u8,       // Exx, Fxx
+    porta_down_memory: u8,  // XM 2xx
+    tone_porta_memory: u8,  // Gxx, see tone_porta_memory()
+    last_note: u8,          // Gxx
+    last_key: u8,           // Key as written in the pattern, before the note map
+    offset_memory: u8,      // Oxx
+    volume_memory: u8,      // Dxy, Kxy, Lxy
+    vol_column_memory: u8,  // a0x, b0x, c0x, d0x
+    retrigger_memory: u8,   // Qxy
+    tremor_memory: u8,      // Ixy
+    tremor_ticks: u8,       // Ixy, position in the on/off cycle
+    tremor_mute: bool,      // Ixy is in its off ticks
+    retrigger_ticks: u8,    // Qxy
+    pattern_loop_row: u16,  // SB0
+    pattern_loop_count: u8, // SBx, repeats left
+    s3m_effect_memory: u8,  // ST3 shares a single memory slot between most effects
+
+    // The offsets are applied on top of freq, volume and panning at mixing time, so
+    // those go back to where they were once the effect stops
+    vibrato: Oscillator, // Depth in Uxy steps, so Hxy's depth is stored 4x
+    vibrato_freq: f32,
+    tremolo: Oscillator,
+    tremolo_volume: f32,
+    panbrello: Oscillator,
+    panbrello_pan: i8,
 
     volume: f32,
-    // panning: i8,
+    panning: u8, // 0..=64, left to right
+
+    volume_envelope_enabled: bool, // S77/S78, only matters if the instrument has one
+    envelope_tick: u16,
+    envelope_volume: f32, // 0..=64
+    released: bool,       // Note off, the sustain loop is let go
+    fading: bool,
+    fade_volume: u16, // 1024 until the note fades out
+    nna: NewNoteAction,
+
+    // Volume ramping
+    ramp_gain: f32, // Gain actually applied, follows the channel's gain over a few ms
+    last_output: f32, // Faded out as the tail once the note stops or is replaced
+    tail: f32,
 }
 
-fn sinc(x: f32) -> f32 {
-    if x <= 0.0001 && x >= -0.0001 {
-        return 1.0;
+// Reads a single sample frame for interpolation. Taps past the loop end wrap to the
+// loop start (or mirror for ping-pong), anything else out of range is silence
+fn tap(sample: &Sample, index: i64) -> f32 {
+    // Same loop bounds as Channel::process, so taps past the end follow the playhead
+    let loop_end = sample.loop_end.min(sample.audio.len() as u32);
+    let looping = !matches!(sample.loop_type, LoopType::None) && loop_end > sample.loop_start;
+
+    let index = if looping && index >= loop_end as i64 {
+        let start = sample.loop_start as i64;
+        let length = loop_end as i64 - start;
+
+        match sample.loop_type {
+            // Turns around on the last sample without repeating it, like the playhead
+            LoopType::PingPong => {
+                let span = length - 1;
+                if span == 0 {
+                    start
+                } else {
+                    let offset = (index - start) % (span * 2);
+                    if offset <= span {
+                        start + offset
+                    } else {
+                        start + span * 2 - offset
+                    }
+                }
+            }
+            _ => start + (index - start) % length,
+        }
+    } else {
+        index
     };
-    (x * PI).sin() / (x * PI)
+
+    if index < 0 || index as usize >= sample.audio.len() {
+        return 0.0;
+    }
+    sample.audio[index as usize] as f32
 }
 
-fn vec_linear(vec: &Vec<i16>, index: f32) -> i16 {
-    (vec[index.floor() as usize] as f32
-        + (index - index.floor())
-            * ((vec[index.ceil() as usize] as f32 - vec[index.floor() as usize] as f32)
-                * (index - index.floor()))) as i16
+fn linear(sample: &Sample, position: f64) -> f32 {
+    let index = position.floor() as i64;
+    let fraction = (position - position.floor()) as f32;
+
+    let a = tap(sample, index);
+    let b = tap(sample, index + 1);
+    a + (b - a) * fraction
 }
 
-fn vec_sinc(vec: &Vec<i16>, quality: i32, index: f32) -> f32 {
-    let ix = index.floor();
-    let fx = index - ix;
-    let mut tmp = 0f32;
+fn windowed_sinc(sample: &Sample, position: f64, half_width: usize, nearest: bool) -> f32 {
+    let table = sinc_table(half_width);
+    let taps = half_width * 2;
 
-    for i in 1 - quality..quality + 1 {
-        tmp += vec[((ix + i as f32 + vec.len() as f32) % vec.len() as f32) as usize] as f32
-            * sinc(i as f32 - fx)
+    let index = position.floor() as i64;
+    let phase = (position - position.floor()) as f32 * SINC_PHASES as f32;
+
+    let (phase, blend) = if nearest {
+        (phase.round() as usize, 0.0)
+    } else {
+        (phase.floor() as usize, phase - phase.floor())
+    };
+    let a = &table[phase * taps..][..taps];
+    let b = &table[(phase + 1).min(SINC_PHASES) * taps..][..taps];
+
+    let mut out = 0f32;
+    for i in 0..taps {
+        let coefficient = a[i] + (b[i] - a[i]) * blend;
+        out += tap(sample, index + i as i64 - (half_width as i64 - 1)) * coefficient;
     }
 
-    tmp
+    out
+}
+
+// Next number from a 32-bit xorshift generator, the state must never be 0
+fn xorshift(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+// Amiga clock used as the reference for period-based (non-linear) pitch math
+pub const PERIOD: f32 = 3579545.25;
+
+// Lowest frequency a slide can bring a channel down to, keeps the period math away from zero
+const MIN_FREQ: f32 = 1.0;
+
+// Highest frequency a slide can take a channel up to, Amiga periods stop at about 3.6
+const MAX_FREQ: f32 = 1_000_000.0;
+
+// Converts a frequency in Hz to an Amiga period
+pub fn period(freq: f32) -> f32 {
+    PERIOD / freq.max(MIN_FREQ)
 }
 
-const PERIOD: f32 = 3579545.25;
+// Converts an Amiga period back to a frequency in Hz, kept within MIN_FREQ..=MAX_FREQ
+pub fn freq_from_period(period: f32) -> f32 {
+    PERIOD / period.clamp(PERIOD / MAX_FREQ, PERIOD / MIN_FREQ)
+}
 
-fn period(freq: f32) -> f32 {
-    PERIOD / freq
+// Moves a frequency's Amiga period by `delta`, periods stay whole like on the Amiga
+fn slide_period(freq: f32, delta: f32) -> f32 {
+    freq_from_period((period(freq) + delta).round())
 }
 
-fn freq_from_period(period: u16) -> f32 {
-    PERIOD / period as f32
+// Frequency in Hz of `note` (60 being C-5) played on a sample whose C-5 speed is `base_frequency`
+pub fn note_frequency(note: u8, base_frequency: u32) -> f32 {
+    2f32.powf((note as f32 - 60.0) / 12.0) * base_frequency as f32
 }
 
 impl Channel<'_> {
+    fn new(module: &Module) -> Channel<'_> {
+        Channel {
+            module,
+
+            current_sample_index: 0,
+            current_instrument: None,
+            playing: false,
+            freq: 8363.0,
+            position: 0.0,
+            backwards: false,
+
+            porta_memory: 0,
+            porta_down_memory: 0,
+            tone_porta_memory: 0,
+            last_note: 0,
+            last_key: 0,
+            offset_memory: 0,
+            volume_memory: 0,
+            vol_column_memory: 0,
+            retrigger_memory: 0,
+            tremor_memory: 0,
+            tremor_ticks: 0,
+            tremor_mute: false,
+            retrigger_ticks: 0,
+            pattern_loop_row: 0,
+            pattern_loop_count: 0,
+            s3m_effect_memory: 0,
+
+            vibrato: Oscillator::new(0),
+            vibrato_freq: 0.0,
+            tremolo: Oscillator::new(1),
+            tremolo_volume: 0.0,
+            panbrello: Oscillator::new(2),
+            panbrello_pan: 0,
+
+            volume: 64.0,
+            panning: 32,
+
+            volume_envelope_enabled: true,
+            envelope_tick: 0,
+            envelope_volume: 64.0,
+            released: false,
+            fading: false,
+            fade_volume: 1024,
+            nna: NewNoteAction::Cut,
+
+            ramp_gain: 0.0,
+            last_output: 0.0,
+            tail: 0.0,
+        }
+    }
+
     fn porta_up(&mut self, linear: bool, mut value: u8) {
-        if value != 0 {
-            self.porta_memory = value;
-        } else {
-            value = self.porta_memory;
+        match self.module.mode {
+            PlaybackMode::S3M => {
+                if value != 0 {
+                    self.s3m_effect_memory = value;
+                } else {
+                    value = self.s3m_effect_memory;
+                }
+            }
+            // ProTracker has no memory here, 0 means 0
+            PlaybackMode::MOD => {}
+            _ => {
+                if value != 0 {
+                    self.porta_memory = value;
+                } else {
+                    value = self.porta_memory;
+                }
+            }
         }
 
         if linear {
@@ -97,16 +346,38 @@ impl Channel<'_> {
             match value & 0xF0 {
                 0xE0 => self.freq += (self.freq / 8363.0) * 8.0 * value as f32,
                 0xF0 => self.freq += (self.freq / 8363.0) * 16.0 * value as f32,
-                _ => self.freq = freq_from_period((period(self.freq) - (value as f32)).round() as u16),
+                _ => self.freq = slide_period(self.freq, -(value as f32)),
             }
         }
+
+        self.freq = self.freq.clamp(MIN_FREQ, MAX_FREQ);
     }
 
     fn porta_down(&mut self, linear: bool, mut value: u8) {
-        if value != 0 {
-            self.porta_memory = value;
-        } else {
-            value = self.porta_memory;
+        match self.module.mode {
+            PlaybackMode::S3M => {
+                if value != 0 {
+                    self.s3m_effect_memory = value;
+                } else {
+                    value = self.s3m_effect_memory;
+                }
+            }
+            PlaybackMode::MOD => {}
+            // XM remembers 1xx and 2xx separately
+            PlaybackMode::XM => {
+                if value != 0 {
+                    self.porta_down_memory = value;
+                } else {
+                    value = self.porta_down_memory;
+                }
+            }
+            _ => {
+                if value != 0 {
+                    self.porta_memory = value;
+                } else {
+                    value = self.porta_memory;
+                }
+            }
         }
 
         if linear {
@@ -122,25 +393,53 @@ impl Channel<'_> {
             match value & 0xF0 {
                 0xE0 => self.freq -= (self.freq / 8363.0) * 8.0 * value as f32,
                 0xF0 => self.freq -= (self.freq / 8363.0) * 16.0 * value as f32,
-                _ => self.freq = freq_from_period((period(self.freq) + (value as f32)).round() as u16),
+                _ => self.freq = slide_period(self.freq, value as f32),
             }
         }
+
+        self.freq = self.freq.clamp(MIN_FREQ, MAX_FREQ);
     }
 
-    fn tone_portamento(&mut self, note: Note, linear: bool, mut value: u8) {
-        if value != 0 {
-            self.porta_memory = value;
-        } else {
-            value = self.porta_memory;
+    fn tone_portamento(&mut self, linear: bool, mut value: u8) {
+        match self.module.mode {
+            PlaybackMode::S3M => {
+                if value != 0 {
+                    self.s3m_effect_memory = value;
+                } else {
+                    value = self.s3m_effect_memory;
+                }
+                // Lxy's portamento half keeps using the last Gxx speed
+                self.tone_porta_memory = value;
+            }
+            _ => {
+                let memory = self.tone_porta_memory();
+                if value != 0 {
+                    *memory = value;
+                } else {
+                    value = *memory;
+                }
+            }
         }
 
-        match note {
-            Note::On(key) => self.last_note = key,
-            _ => {}
+        self.slide_to_note(linear, value);
+    }
+
+    // IT links Gxx's memory with Exx/Fxx unless Compatible Gxx is on
+    fn tone_porta_memory(&mut self) -> &mut u8 {
+        match self.module.mode {
+            PlaybackMode::IT | PlaybackMode::ITSample if !self.module.compatible_gxx => {
+                &mut self.porta_memory
+            }
+            _ => &mut self.tone_porta_memory,
         }
+    }
 
-        let desired_freq = 2f32.powf((self.last_note as f32 - 60.0) / 12.0)
-            * self.module.samples[self.current_sample_index as usize].base_frequency as f32;
+    // Glides towards last_note, which the row's note (through the note map) sets
+    fn slide_to_note(&mut self, linear: bool, value: u8) {
+        let desired_freq = note_frequency(
+            self.last_note,
+            self.module.samples[self.current_sample_index as usize].base_frequency,
+        );
 
         if linear {
             if self.freq < desired_freq {
@@ -157,12 +456,12 @@ impl Channel<'_> {
         } else {
             // Amiga slides
             if self.freq < desired_freq {
-                self.freq = freq_from_period((period(self.freq) - (value as f32)).round() as u16);
+                self.freq = slide_period(self.freq, -(value as f32));
                 if self.freq > desired_freq {
                     self.freq = desired_freq
                 }
             } else if self.freq > desired_freq {
-                self.freq = freq_from_period((period(self.freq) + (value as f32)).round() as u16);
+                self.freq = slide_period(self.freq, value as f32);
                 if self.freq < desired_freq {
                     self.freq = desired_freq
                 }
@@ -171,10 +470,22 @@ impl Channel<'_> {
     }
 
     fn vol_slide(&mut self, mut value: u8) {
-        if value != 0 {
-            self.volume_memory = value;
-        } else {
-            value = self.volume_memory;
+        match self.module.mode {
+            PlaybackMode::S3M => {
+                if value != 0 {
+                    self.s3m_effect_memory = value;
+                } else {
+                    value = self.s3m_effect_memory;
+                }
+            }
+            PlaybackMode::MOD => {}
+            _ => {
+                if value != 0 {
+                    self.volume_memory = value;
+                } else {
+                    value = self.volume_memory;
+                }
+            }
         }
 
         let upper = (value & 0xF0) >> 4;
@@ -207,109 +518,542 @@ impl Channel<'_> {
         }
     }
 
-    fn retrigger(&mut self, value: u8) {
-        match (value & 0xF0) >> 4 {
-            // Volume change
-            // TODO last used value for XM
-            //0 => {}
-            1 => self.volume -= 1.0,
-            2 => self.volume -= 2.0,
-            3 => self.volume -= 4.0,
-            4 => self.volume -= 8.0,
-            5 => self.volume -= 16.0,
-            6 => self.volume *= 2.0 / 3.0,
-            7 => self.volume *= 0.5,
-
-            9 => self.volume += 1.0,
-            0xA => self.volume += 2.0,
-            0xB => self.volume += 4.0,
-            0xC => self.volume += 8.0,
-            0xD => self.volume += 16.0,
-            0xE => self.volume *= 1.5,
-            0xF => self.volume *= 2.0,
+    fn vibrato(&mut self, linear: bool, mut value: u8, fine: bool) {
+        if let PlaybackMode::S3M = self.module.mode {
+            if value != 0 {
+                self.s3m_effect_memory = value;
+            } else {
+                value = self.s3m_effect_memory;
+            }
+        }
+
+        self.vibrato.set_parameters(value, if fine { 1 } else { 4 });
+        self.update_vibrato(linear);
+    }
 
-            _ => {}
+    // Steps the vibrato with the last speed and depth, Kxy does only this
+    fn update_vibrato(&mut self, linear: bool) {
+        let delta = self.vibrato.step() / 128.0;
+
+        // delta is in 1/64 semitones, or quarter Amiga periods
+        let freq = if linear {
+            self.freq * 2f32.powf(delta / 768.0)
+        } else {
+            PERIOD / (period(self.freq) - delta / 4.0).max(1.0)
+        };
+        self.vibrato_freq = freq - self.freq;
+    }
+
+    fn tremolo(&mut self, mut value: u8) {
+        if let PlaybackMode::S3M = self.module.mode {
+            if value != 0 {
+                self.s3m_effect_memory = value;
+            } else {
+                value = self.s3m_effect_memory;
+            }
+        }
+
+        // Up to +-15 volume at depth F
+        self.tremolo.set_parameters(value, 1);
+        self.tremolo_volume = self.tremolo.step() / 64.0;
+    }
+
+    fn panbrello(&mut self, value: u8) {
+        // Up to +-30 panning at depth F
+        self.panbrello.set_parameters(value, 1);
+        self.panbrello_pan = (self.panbrello.step() / 32.0) as i8;
+    }
+
+    fn output_panning(&self) -> u8 {
+        (self.panning as i16 + self.panbrello_pan as i16).clamp(0, 64) as u8
+    }
+
+    // Volume column slides, which don't share Dxy's memory and are always whole steps
+    fn vol_column_slide(&mut self, mut value: u8, up: bool) {
+        if value != 0 {
+            self.vol_column_memory = value;
+        } else {
+            value = self.vol_column_memory;
+        }
+
+        if up {
+            self.volume = (self.volume + value as f32).min(64.0);
+        } else {
+            self.volume = (self.volume - value as f32).max(0.0);
+        }
+    }
+
+    // Gates the note, x ticks on then y ticks off (0 counts as 1). Only the output is
+    // muted, the volume itself is left alone
+    fn tremor(&mut self, mut value: u8) {
+        match self.module.mode {
+            PlaybackMode::S3M => {
+                if value != 0 {
+                    self.s3m_effect_memory = value;
+                } else {
+                    value = self.s3m_effect_memory;
+                }
+            }
+            _ => {
+                if value != 0 {
+                    self.tremor_memory = value;
+                } else {
+                    value = self.tremor_memory;
+                }
+            }
+        }
+
+        let on = ((value & 0xF0) >> 4).max(1);
+        let off = (value & 0x0F).max(1);
+
+        self.tremor_ticks %= on + off;
+        self.tremor_mute = self.tremor_ticks >= on;
+        self.tremor_ticks += 1;
+    }
+
+    fn retrigger(&mut self, mut value: u8) {
+        match self.module.mode {
+            PlaybackMode::S3M => {
+                if value != 0 {
+                    self.s3m_effect_memory = value;
+                } else {
+                    value = self.s3m_effect_memory;
+                }
+            }
+            // E90 doesn't retrigger
+            PlaybackMode::MOD if value & 0x0F == 0 => return,
+            PlaybackMode::MOD => {}
+            // XM remembers the volume change and the interval apart, so R03 after R91
+            // keeps adding 1
+            PlaybackMode::XM => {
+                let last = self.retrigger_memory;
+                if value & 0xF0 == 0 {
+                    value |= last & 0xF0;
+                }
+                if value & 0x0F == 0 {
+                    value |= last & 0x0F;
+                }
+                self.retrigger_memory = value;
+            }
+            _ => {
+                if value != 0 {
+                    self.retrigger_memory = value;
+                } else {
+                    value = self.retrigger_memory;
+                }
+            }
         }
 
         if self.retrigger_ticks >= value & 0x0F {
             self.position = 0.0;
             self.retrigger_ticks = 0;
+
+            // The volume only changes when the note actually retriggers. IT, S3M and XM
+            // work in whole volume steps and clamp to 0..=64
+            self.volume = match (value & 0xF0) >> 4 {
+                1 => self.volume - 1.0,
+                2 => self.volume - 2.0,
+                3 => self.volume - 4.0,
+                4 => self.volume - 8.0,
+                5 => self.volume - 16.0,
+                6 => (self.volume * 2.0 / 3.0).floor(),
+                7 => (self.volume / 2.0).floor(),
+
+                9 => self.volume + 1.0,
+                0xA => self.volume + 2.0,
+                0xB => self.volume + 4.0,
+                0xC => self.volume + 8.0,
+                0xD => self.volume + 16.0,
+                0xE => (self.volume * 3.0 / 2.0).floor(),
+                0xF => self.volume * 2.0,
+
+                _ => self.volume,
+            }
+            .clamp(0.0, 64.0);
         };
 
         self.retrigger_ticks += 1;
+    }
 
-        if self.volume > 64.0 {
-            self.volume = 64.0
+    // Runs on every tick, including the first of a row
+    fn update_envelope(&mut self) {
+        let Some(instrument) = self
+            .current_instrument
+            .and_then(|i| self.module.instruments.get(i as usize))
+        else {
+            return;
         };
-        if self.volume < 0.0 {
-            self.volume = 0.0
+        let envelope = &instrument.volume_envelope;
+
+        if self.playing && self.volume_envelope_active() {
+            self.envelope_volume = envelope.value(self.envelope_tick);
+            self.envelope_tick += 1;
+
+            let last = *envelope.nodes.last().unwrap();
+            if envelope.sustain_enabled && !self.released {
+                if self.envelope_tick > envelope.node_tick(envelope.sustain_end) {
+                    self.envelope_tick = envelope.node_tick(envelope.sustain_start);
+                }
+            } else if envelope.loop_enabled {
+                if self.envelope_tick > envelope.node_tick(envelope.loop_end) {
+                    self.envelope_tick = envelope.node_tick(envelope.loop_start);
+                }
+            } else if self.envelope_tick > last.0 {
+                // The envelope holds its last node, if that's 0 the note is over
+                self.envelope_tick = last.0;
+                if last.1 == 0 {
+                    self.playing = false;
+                } else if self.released {
+                    self.fading = true;
+                }
+            }
+        } else {
+            self.envelope_volume = 64.0;
+        }
+
+        if self.fading {
+            self.fade_volume = self.fade_volume.saturating_sub(instrument.fadeout);
+            if self.fade_volume == 0 {
+                self.playing = false;
+            }
+        }
+    }
+
+    fn volume_envelope_active(&self) -> bool {
+        self.volume_envelope_enabled
+            && self
+                .current_instrument
+                .and_then(|i| self.module.instruments.get(i as usize))
+                .is_some_and(|i| i.volume_envelope.enabled && !i.volume_envelope.nodes.is_empty())
+    }
+
+    fn note_off(&mut self) {
+        let Some(instrument) = self
+            .current_instrument
+            .and_then(|i| self.module.instruments.get(i as usize))
+        else {
+            // Without an instrument there's nothing to release
+            self.playing = false;
+            return;
+        };
+
+        self.released = true;
+        // Without an envelope to run out (or with one that never does) it fades right away
+        if !self.volume_envelope_active() || instrument.volume_envelope.loop_enabled {
+            self.fading = true;
+        }
+    }
+
+    fn apply_action(&mut self, action: NewNoteAction) {
+        match action {
+            NewNoteAction::Cut => self.playing = false,
+            NewNoteAction::Continue => {}
+            NewNoteAction::Off => self.note_off(),
+            NewNoteAction::Fade => self.fading = true,
+        }
+    }
+
+    fn note_cut(&mut self) {
+        match self.module.mode {
+            // IT only silences the note, a later volume command on it brings it back
+            PlaybackMode::IT | PlaybackMode::ITSample => self.volume = 0.0,
+            _ => self.playing = false,
         }
     }
 
-    fn process(&mut self, samplerate: u32, interpolation: Interpolation) -> i32 {
-        let sample = &self.module.samples[self.current_sample_index as usize];
-        if !self.playing || sample.audio.len() == 0 {
-            return 0;
+    // Starts `key` on a sample (0-based) from its beginning, along with the envelope
+    // and everything else that follows a note
+    fn note_on(&mut self, instrument: Option<u8>, key: u8, sample: u8) {
+        let carry = self.playing
+            && self.current_instrument == instrument
+            && instrument
+                .and_then(|i| self.module.instruments.get(i as usize))
+                .is_some_and(|i| i.volume_envelope.carry);
+
+        self.ramp_restart();
+        self.current_sample_index = sample;
+        self.current_instrument = instrument;
+        self.nna = instrument
+            .and_then(|i| self.module.instruments.get(i as usize))
+            .map_or(NewNoteAction::Cut, |i| i.new_note_action);
+        self.last_note = key;
+        self.playing = true;
+
+        self.volume_envelope_enabled = true;
+        if !carry {
+            self.envelope_tick = 0;
+            self.envelope_volume = 64.0;
+        }
+        self.released = false;
+        self.fading = false;
+        self.fade_volume = 1024;
+
+        self.vibrato.note_on();
+        self.tremolo.note_on();
+        self.panbrello.note_on();
+        self.vibrato_freq = 0.0;
+        self.backwards = false;
+        self.position = 0.0;
+        self.freq = note_frequency(key, self.module.samples[sample as usize].base_frequency);
+    }
+
+    // Starts a new note from silence, whatever was playing fades out as the tail
+    fn ramp_restart(&mut self) {
+        self.tail += self.last_output;
+        self.last_output = 0.0;
+        self.ramp_gain = 0.0;
+    }
+
+    fn is_audible(&self) -> bool {
+        self.playing || self.tail != 0.0 || self.last_output != 0.0
+    }
+
+    fn process(
+        &mut self,
+        samplerate: u32,
+        interpolation: Interpolation,
+        ramping: bool,
+        global_volume: u8,
+    ) -> i32 {
+        // Ramps take 5ms from silence to full scale
+        let step = 1.0 / (samplerate as f32 * 0.005);
+
+        if !self.playing {
+            self.tail += self.last_output;
+            self.last_output = 0.0;
+        }
+        // Full scale output is a 16-bit sample at full gain, times 32768
+        let tail = if ramping { self.tail } else { 0.0 };
+        self.tail = if self.tail > 0.0 {
+            (self.tail - step * 32768.0 * 32768.0).max(0.0)
+        } else {
+            (self.tail + step * 32768.0 * 32768.0).min(0.0)
+        };
+        if !ramping {
+            self.tail = 0.0;
+        }
+
+        let Some(sample) = self.module.samples.get(self.current_sample_index as usize) else {
+            return tail as i32;
         };
+        if !self.playing || sample.audio.is_empty() {
+            return tail as i32;
+        };
+
+        let freq = (self.freq + self.vibrato_freq).max(MIN_FREQ);
+        let delta = freq as f64 / samplerate as f64;
+
+        // Loops running past the end of the audio are cut short to it. Zero-length or
+        // inverted loops would never advance, play those as unlooped
+        let loop_end = sample.loop_end.min(sample.audio.len() as u32);
+        let looping = !matches!(sample.loop_type, LoopType::None) && loop_end > sample.loop_start;
 
         if self.backwards {
-            if self.position as u32 <= sample.loop_start {
-                self.backwards = false
-            } else {
-                self.position -= self.freq as f64 / samplerate as f64;
-            }
+            self.position -= delta;
         } else {
-            self.position += self.freq as f64 / samplerate as f64;
+            self.position += delta;
         }
 
-        if sample.loop_end > 0 {
-            if self.position as u32 > sample.loop_end - 1 {
-                match sample.loop_type {
-                    LoopType::Forward => self.position = sample.loop_start as f64,
-                    LoopType::PingPong => {
-                        self.backwards = true;
-                        self.position -= self.freq as f64 / samplerate as f64;
-                    } // self.position -= 1.0 or 2.0 does not work as the program errors with out of bounds
-                    _ => {}
+        if looping {
+            let start = sample.loop_start as f64;
+            let end = loop_end as f64;
+
+            match sample.loop_type {
+                LoopType::PingPong => {
+                    // Turns around on the last sample and loop_start. Folding the distance
+                    // travelled keeps steps longer than the loop inside it too
+                    let span = end - 1.0 - start;
+                    if span <= 0.0 {
+                        self.position = start;
+                    } else if (self.backwards && self.position < start)
+                        || (!self.backwards && self.position > end - 1.0)
+                    {
+                        let travelled = if self.backwards {
+                            span * 2.0 - (self.position - start)
+                        } else {
+                            self.position - start
+                        };
+                        let travelled = travelled.rem_euclid(span * 2.0);
+
+                        self.backwards = travelled > span;
+                        self.position = if self.backwards {
+                            start + span * 2.0 - travelled
+                        } else {
+                            start + travelled
+                        };
+                    }
+                }
+                _ => {
+                    if self.position >= end {
+                        self.position = start + (self.position - start) % (end - start);
+                    }
                 }
             }
-        }
-
-        // Prevent out of bounds, but it doesn't seem to be working reliably
-        if self.position as usize >= sample.audio.len() - 1
-            && matches!(sample.loop_type, LoopType::None)
-        {
+        } else if self.position >= sample.audio.len() as f64 {
             self.playing = false;
             self.backwards = false;
+            return tail as i32;
         }
 
-        if !self.playing {
-            return 0;
+        // Note volume and sample global volume are both 0..=64, the song's global volume
+        // 0..=128. All of them are only applied here
+        let gain = ((self.volume + self.tremolo_volume).clamp(0.0, 64.0) / 64.0)
+            * (sample.global_volume as f32 / 64.0)
+            * (global_volume as f32 / 128.0)
+            * (self.envelope_volume / 64.0)
+            * (self.fade_volume as f32 / 1024.0)
+            * if self.tremor_mute { 0.0 } else { 1.0 };
+
+        let value = match interpolation {
+            Interpolation::None => tap(sample, self.position as i64),
+            Interpolation::Linear => linear(sample, self.position),
+            Interpolation::Sinc16 => windowed_sinc(sample, self.position, 16, false),
+            Interpolation::Sinc32 => windowed_sinc(sample, self.position, 32, false),
+            Interpolation::Sinc64 => windowed_sinc(sample, self.position, 64, false),
+            Interpolation::Sinc64Fast => windowed_sinc(sample, self.position, 64, true),
         };
 
-        match interpolation {
-            Interpolation::None => {
-                (((sample.audio[self.position as usize]) as i32 * 32768) as f32
-                    * (self.volume as f32 / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
-            }
-            Interpolation::Linear => {
-                (((vec_linear(&sample.audio, (self.position - 1.0) as f32)) as i32 * 32768) as f32
-                    * (self.volume / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
-            }
-            Interpolation::Sinc16 => {
-                (((vec_sinc(&sample.audio, 16, self.position as f32)) as i32 * 32768) as f32
-                    * (self.volume as f32 / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
+        self.ramp_gain = if !ramping {
+            gain
+        } else if self.ramp_gain < gain {
+            (self.ramp_gain + step).min(gain)
+        } else {
+            (self.ramp_gain - step).max(gain)
+        };
+
+        self.last_output = value * 32768.0 * self.ramp_gain;
+        (self.last_output + tail) as i32
+    }
+}
+
+// Note and sample (1-based, 0 = none) an instrument's note map gives for a key
+fn map_note(module: &Module, instrument: Option<u8>, key: u8) -> Option<(u8, u8)> {
+    module
+        .instruments
+        .get(instrument? as usize)?
+        .sample_map
+        .get(key as usize)
+        .copied()
+}
+
+// Balance pan law, a centred channel plays at full level on both sides and panning
+// only turns the far side down, to exactly nothing at either end
+fn pan(value: i32, panning: u8) -> (i32, i32) {
+    let left = value as i64 * (64 - panning as i64).clamp(0, 32) / 32;
+    let right = value as i64 * (panning as i64).min(32) / 32;
+    (left as i32, right as i32)
+}
+
+fn effect_supported(effect: &Effect) -> bool {
+    matches!(
+        effect,
+        Effect::None
+            | Effect::SetSpeed(_)
+            | Effect::PosJump(_)
+            | Effect::PatBreak(_)
+            | Effect::VolSlide(_)
+            | Effect::PortaDown(_)
+            | Effect::PortaUp(_)
+            | Effect::TonePorta(_)
+            | Effect::Vibrato(_)
+            | Effect::VolSlideVibrato(_)
+            | Effect::VolSlideTonePorta(_)
+            | Effect::SampleOffset(_)
+            | Effect::Tremor(_)
+            | Effect::Retrig(_)
+            | Effect::Tremolo(_)
+            | Effect::SetVibratoWaveform(_)
+            | Effect::SetTremoloWaveform(_)
+            | Effect::SetPanbrelloWaveform(_)
+            | Effect::SetPan(_)
+            | Effect::NoteCut(_)
+            | Effect::NoteDelay(_)
+            | Effect::PatLoopStart
+            | Effect::PatDelay(_)
+            | Effect::FinePatternDelay(_)
+            | Effect::VolEnvOff
+            | Effect::VolEnvOn
+            | Effect::PastNoteCut
+            | Effect::PastNoteOff
+            | Effect::PastNoteFade
+            | Effect::NNANoteCut
+            | Effect::NNANoteContinue
+            | Effect::NNANoteOff
+            | Effect::NNANoteFade
+            | Effect::PatLoop(_)
+            | Effect::SetTempo(_)
+            | Effect::FineVibrato(_)
+            | Effect::FineSetPan(_)
+            | Effect::Panbrello(_)
+            | Effect::SetGlobalVol(_)
+    )
+}
+
+// g0x's speeds, as Gxx values
+const VOL_COLUMN_PORTA: [u8; 10] = [0, 1, 4, 8, 16, 32, 64, 96, 128, 255];
+
+fn vol_effect_supported(effect: &VolEffect) -> bool {
+    matches!(
+        effect,
+        VolEffect::None
+            | VolEffect::FineVolSlideUp(_)
+            | VolEffect::FineVolSlideDown(_)
+            | VolEffect::VolSlideUp(_)
+            | VolEffect::VolSlideDown(_)
+            | VolEffect::PortaDown(_)
+            | VolEffect::PortaUp(_)
+            | VolEffect::TonePorta(_)
+            | VolEffect::VibratoDepth(_)
+            | VolEffect::SetPan(_)
+            | VolEffect::Volume(_)
+    )
+}
+
+// Lists every effect used in the module by name, along with whether the player
+// implements it, so it's clear up front if a module won't play back right
+pub fn effect_coverage(module: &Module) -> Vec<(String, bool)> {
+    let mut coverage = Vec::<(String, bool)>::new();
+
+    for col in module.patterns.iter().flatten().flatten() {
+        let effects = [
+            (format!("{:?}", col.effect), effect_supported(&col.effect)),
+            (format!("Vol{:?}", col.vol), vol_effect_supported(&col.vol)),
+        ];
+
+        for (name, supported) in effects {
+            // Drop the value, only the kind of effect matters here
+            let name = name.split('(').next().unwrap().to_string();
+            if name == "None" || name == "VolNone" {
+                continue;
             }
-            Interpolation::Sinc32 => {
-                (((vec_sinc(&sample.audio, 32, self.position as f32)) as i32 * 32768) as f32
-                    * (self.volume as f32 / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
+
+            if !coverage.iter().any(|(n, _)| *n == name) {
+                coverage.push((name, supported));
             }
         }
     }
+
+    coverage
+}
+
+// Past IT's 256 voices (with the 64 channels) the quietest background note is dropped
+const MAX_BACKGROUND_VOICES: usize = 192;
+
+// Makes room for one more voice in a full pool by dropping the quietest one. Voices
+// already ramping out count as silent, among equals the oldest goes
+fn make_room(voices: &mut Vec<(u8, Channel)>, max: usize) {
+    if voices.len() < max {
+        return;
+    }
+    let loudness = |c: &Channel| if c.playing { c.ramp_gain } else { 0.0 };
+    let quietest = voices
+        .iter()
+        .enumerate()
+        .min_by(|(_, (_, a)), (_, (_, b))| loudness(a).total_cmp(&loudness(b)))
+        .map(|(i, _)| i);
+    if let Some(i) = quietest {
+        voices.remove(i);
+    }
 }
 
 pub struct Player<'a> {
@@ -317,6 +1061,19 @@ pub struct Player<'a> {
 
     pub samplerate: u32,
     pub interpolation: Interpolation,
+    pub ramping: bool,        // Volume ramping against clicks, on by default
+    pub print_position: bool, // Show the position on stdout as rows play, on by default
+    pub dither: bool,         // TPDF dither where render_to_wav cuts to 16 bits, off by default
+    dither_state: u32,
+
+    // Lo-fi master effects, both off by default
+    pub bitcrush: u8,    // Bits kept in the output, 0 = off
+    pub downsample: u32, // Each output frame is held for this many frames, 0 or 1 = off
+    held_frame: (i32, i32),
+    hold_counter: u32,
+
+    duck_gain: f32,
+    duck_target: f32,
 
     pub current_position: u8,
     pub current_pattern: u8,
@@ -324,98 +1081,569 @@ pub struct Player<'a> {
 
     current_tempo: u8,
     current_speed: u8,
+    current_global_volume: u8,
 
     tick_counter: u32,
     ticks_passed: u8,
+    preview_tick_counter: u32,
+
+    pattern_delay: u8,        // SEx, times the row is played again
+    pattern_delay_passed: u8, // Repeats done so far
+    fine_pattern_delay: u8,   // S6x, extra ticks on each repeat of the row
+
+    ab_loop: (u8, u16, u8, u16), // start position, start row, end position, end row
+    ab_loop_enabled: bool,
+
+    finished: bool, // End of song reached, output is silent from here on
+    looping: bool,  // Restart from order 0 at the end of song instead
+    paused: bool,
+    elapsed_samples: u64, // Frames played
 
     channels: [Channel<'a>; 64],
+    preview_voices: Vec<(u8, Channel<'a>)>, // keyed by the note that triggered them
+    background_voices: Vec<(u8, Channel<'a>)>, // Notes moved off their channel by NNAs, keyed by channel
 }
 
-impl Player<'_> {
+impl<'a> Player<'a> {
     pub fn from_module(module: &Module, samplerate: u32) -> Player<'_> {
         Player {
             module,
 
             samplerate,
             interpolation: Interpolation::Linear,
+            ramping: true,
+            print_position: true,
+            dither: false,
+            dither_state: 0x9E3779B9,
+
+            bitcrush: 0,
+            downsample: 0,
+            held_frame: (0, 0),
+            hold_counter: 0,
+
+            duck_gain: 1.0,
+            duck_target: 1.0,
 
             current_position: 0,
             current_pattern: module.playlist[0],
             current_row: 65535,
 
             current_tempo: module.initial_tempo,
-            current_speed: module.initial_speed,
+            current_speed: if module.initial_speed != 0 {
+                module.initial_speed
+            } else {
+                6
+            },
+            current_global_volume: module.initial_global_volume,
 
             tick_counter: 0,
             ticks_passed: 0,
+            preview_tick_counter: 0,
+
+            pattern_delay: 0,
+            pattern_delay_passed: 0,
+            fine_pattern_delay: 0,
+
+            ab_loop: (0, 0, 0, 0),
+            ab_loop_enabled: false,
+
+            finished: false,
+            looping: false,
+            paused: false,
+            elapsed_samples: 0,
+
+            channels: array::from_fn(|i| {
+                let mut channel = Channel::new(module);
+                // Every channel gets its own random waveforms
+                let seed = i as u32 * 3;
+                channel.vibrato.random = Oscillator::new(seed).random;
+                channel.tremolo.random = Oscillator::new(seed + 1).random;
+                channel.panbrello.random = Oscillator::new(seed + 2).random;
+                // Surround (100) isn't supported, it plays in the middle
+                if module.initial_panning[i] <= 64 {
+                    channel.panning = module.initial_panning[i];
+                }
+                channel
+            }),
+            preview_voices: Vec::new(),
+            background_voices: Vec::new(),
+        }
+    }
 
-            channels: array::from_fn(|_| Channel {
-                module: module,
+    // Auditions an instrument (a sample outside of instrument mode, both 0-based) on its
+    // own voice outside of the song, e.g. from a virtual keyboard. Each key gets its own
+    // voice so several can sound at once. Notes go through the note map, envelopes and
+    // NNA just like in the song
+    pub fn preview_note_on(&mut self, key: u8, instrument: u8) {
+        let (instrument, note, sample) = match self.module.mode {
+            PlaybackMode::IT => match map_note(self.module, Some(instrument), key) {
+                Some((note, sample)) => (Some(instrument), note, sample),
+                None => return,
+            },
+            _ => (None, key, instrument.saturating_add(1)),
+        };
+        if sample == 0 || sample as usize > self.module.samples.len() {
+            return;
+        }
 
-                current_sample_index: 0,
-                playing: false,
-                freq: 8363.0,
-                position: 0.0,
-                backwards: false,
+        // Pressing a key again moves its last note on with its NNA, as on a channel
+        for (_, voice) in self.preview_voices.iter_mut().filter(|(k, _)| *k == key) {
+            voice.apply_action(voice.nna);
+        }
 
-                porta_memory: 0,
-                last_note: 0,
-                offset_memory: 0,
-                volume_memory: 0,
-                retrigger_ticks: 0,
+        let mut voice = Channel::new(self.module);
+        voice.volume = self.module.samples[sample as usize - 1].default_volume as f32;
+        voice.note_on(instrument, note, sample - 1);
+        // Held or ringing previews past this many drop the quietest
+        const MAX_PREVIEW_VOICES: usize = 64;
+        make_room(&mut self.preview_voices, MAX_PREVIEW_VOICES);
+        self.preview_voices.push((key, voice));
+    }
 
-                volume: 64.0,
-                // panning: 0
-            }),
+    pub fn preview_note_off(&mut self, key: u8) {
+        // Released rather than removed, so it can fade and ramp out
+        for (_, voice) in self.preview_voices.iter_mut().filter(|(k, _)| *k == key) {
+            voice.note_off();
         }
     }
 
-    pub fn process(&mut self) -> i32 {
-        let mut out = 0i32;
-
-        for c in self.channels.iter_mut() {
-            if c.playing {
-                out = out.saturating_add(c.process(self.samplerate, self.interpolation));
-            }
+    // Loops playback between two points, inclusive of the end row. Only the
+    // sequencing wraps around, notes keep playing across the jump. Returns false and
+    // leaves the loop alone if either point isn't a row of a playable order
+    pub fn set_ab_loop(
+        &mut self,
+        start_order: u8,
+        start_row: u16,
+        end_order: u8,
+        end_row: u16,
+    ) -> bool {
+        let row_exists = |order: u8, row: u16| {
+            self.resolve_order(order as usize) == Some(order as usize)
+                && (row as usize)
+                    < self.module.patterns[self.module.playlist[order as usize] as usize].len()
+        };
+        if !row_exists(start_order, start_row) || !row_exists(end_order, end_row) {
+            return false;
         }
 
-        if self.tick_counter >= ((self.samplerate as f32 * 2.5) / self.current_tempo as f32) as u32
-        {
-            self.ticks_passed += 1;
-            self.tick_counter = 0;
-            if self.ticks_passed >= self.current_speed {
-                self.advance_row();
-                self.play_row();
-            }
-            self.process_tick();
-        } else {
-            self.tick_counter += 1;
-        }
+        self.ab_loop = (start_order, start_row, end_order, end_row);
+        self.ab_loop_enabled = true;
+        true
+    }
 
-        out
+    pub fn set_ab_loop_enabled(&mut self, enabled: bool) {
+        self.ab_loop_enabled = enabled;
     }
 
-    fn process_tick(&mut self) {
-        if self.current_row == 65535 {
-            return;
-        };
-        let row = &self.module.patterns[self.current_pattern as usize][self.current_row as usize];
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
 
-        if self.ticks_passed <= 0 {return};
+    // Whether the song restarts from order 0 when it ends, off by default
+    pub fn set_looping(&mut self, loop_song: bool) {
+        self.looping = loop_song;
+        if loop_song && self.finished {
+            self.finished = false;
+            self.current_row = 65535;
+            self.set_position(0);
+        }
+    }
 
-        for (i, col) in row.iter().enumerate() {
-            let channel = &mut self.channels[i];
+    // Jumps to a row of an order (the next playable one if it's +++), cutting any
+    // playing notes. Returns false and stays put if the order is past the end
+    pub fn seek(&mut self, order: usize, row: u16) -> bool {
+        let Some(order) = self.resolve_order(order) else {
+            return false;
+        };
 
-            match col.effect {
+        for c in self.channels.iter_mut() {
+            c.playing = false;
+        }
+        self.background_voices.clear();
+
+        self.current_position = order as u8;
+        self.current_pattern = self.module.playlist[order];
+        self.reset_pattern_loops();
+        let rows = self.module.patterns[self.current_pattern as usize].len() as u16;
+        self.current_row = row.min(rows.saturating_sub(1));
+        self.tick_counter = 0;
+        self.ticks_passed = 0;
+        self.finished = false;
+
+        self.play_row();
+        true
+    }
+
+    // Current order and row
+    pub fn position(&self) -> (u8, u16) {
+        if self.current_row == 65535 {
+            (self.current_position, 0)
+        } else {
+            (self.current_position, self.current_row)
+        }
+    }
+
+    // While paused the song is silent and nothing in it advances, previews still play
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn tempo(&self) -> u8 {
+        self.current_tempo
+    }
+
+    pub fn speed(&self) -> u8 {
+        self.current_speed
+    }
+
+    pub fn elapsed_samples(&self) -> u64 {
+        self.elapsed_samples
+    }
+
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.interpolation = match quality {
+            Quality::Fast => Interpolation::None,
+            Quality::Balanced => Interpolation::Linear,
+            Quality::High => Interpolation::Sinc64,
+        }
+    }
+
+    pub fn channel_state(&self, channel: usize) -> Option<ChannelState> {
+        let c = self.channels.get(channel)?;
+
+        Some(ChannelState {
+            playing: c.playing,
+            sample: c.current_sample_index,
+            freq: c.freq + c.vibrato_freq,
+            // The gain the mixer last applied, which is already ramped
+            volume: if c.playing { c.ramp_gain * 64.0 } else { 0.0 },
+            pan: c.output_panning(),
+        })
+    }
+
+    // Master gain on top of the song's own volume, e.g. to duck the music under
+    // dialogue. Meant to be driven by the caller every buffer, changes are ramped
+    // over 5ms so they don't click
+    pub fn set_duck_gain(&mut self, gain: f32) {
+        self.duck_target = gain.max(0.0);
+    }
+
+    // Called before playback these override the module's initial values,
+    // the song's own Axx/Txx/Vxx still take over once they're reached
+    pub fn set_tempo(&mut self, tempo: u8) {
+        self.current_tempo = tempo.max(32)
+    }
+
+    pub fn set_speed(&mut self, speed: u8) {
+        if speed != 0 {
+            self.current_speed = speed
+        }
+    }
+
+    pub fn set_global_volume(&mut self, volume: u8) {
+        self.current_global_volume = volume.min(128)
+    }
+
+    // Renders one stereo frame
+    pub fn process(&mut self) -> (i32, i32) {
+        // Previews don't belong to the song, so they keep playing while it's paused or
+        // after it ends, and their envelopes run on their own ticks
+        let mut left = 0i32;
+        let mut right = 0i32;
+
+        for (_, c) in self.preview_voices.iter_mut() {
+            let (l, r) = pan(
+                c.process(
+                    self.samplerate,
+                    self.interpolation,
+                    self.ramping,
+                    self.current_global_volume,
+                ),
+                c.output_panning(),
+            );
+            left = left.saturating_add(l);
+            right = right.saturating_add(r);
+        }
+        self.preview_voices.retain(|(_, c)| c.is_audible());
+
+        if self.preview_tick_counter >= self.tick_length() {
+            self.preview_tick_counter = 0;
+            for (_, c) in self.preview_voices.iter_mut() {
+                c.update_envelope();
+            }
+        } else {
+            self.preview_tick_counter += 1;
+        }
+
+        if self.finished || self.paused {
+            return self.master((left, right));
+        }
+        self.elapsed_samples += 1;
+
+        for c in self.channels.iter_mut() {
+            if c.is_audible() {
+                let (l, r) = pan(
+                    c.process(
+                        self.samplerate,
+                        self.interpolation,
+                        self.ramping,
+                        self.current_global_volume,
+                    ),
+                    c.output_panning(),
+                );
+                left = left.saturating_add(l);
+                right = right.saturating_add(r);
+            }
+        }
+
+        for (_, c) in self.background_voices.iter_mut() {
+            let (l, r) = pan(
+                c.process(
+                    self.samplerate,
+                    self.interpolation,
+                    self.ramping,
+                    self.current_global_volume,
+                ),
+                c.output_panning(),
+            );
+            left = left.saturating_add(l);
+            right = right.saturating_add(r);
+        }
+        self.background_voices.retain(|(_, c)| c.is_audible());
+
+        if self.tick_counter >= self.tick_length() {
+            self.ticks_passed += 1;
+            self.tick_counter = 0;
+            if self.ticks_passed >= self.current_speed.saturating_add(self.fine_pattern_delay) {
+                if self.pattern_delay_passed < self.pattern_delay {
+                    // Only the ticks repeat, the row's notes and Axx/Txx aren't played again
+                    self.pattern_delay_passed += 1;
+                    self.ticks_passed = 0;
+                } else {
+                    self.advance_row();
+                    self.play_row();
+                }
+            }
+            for c in self.channels.iter_mut() {
+                c.update_envelope();
+            }
+            for (_, c) in self.background_voices.iter_mut() {
+                c.update_envelope();
+            }
+            self.process_tick();
+        } else {
+            self.tick_counter += 1;
+        }
+
+        self.master((left, right))
+    }
+
+    // Master gain and lo-fi effects on top of the mix
+    fn master(&mut self, (left, right): (i32, i32)) -> (i32, i32) {
+        let step = 1.0 / (self.samplerate as f32 * 0.005);
+        if self.duck_gain < self.duck_target {
+            self.duck_gain = (self.duck_gain + step).min(self.duck_target);
+        } else if self.duck_gain > self.duck_target {
+            self.duck_gain = (self.duck_gain - step).max(self.duck_target);
+        }
+
+        self.lofi((
+            (left as f32 * self.duck_gain) as i32,
+            (right as f32 * self.duck_gain) as i32,
+        ))
+    }
+
+    // Plays from the current position (the start of the song if nothing has played yet)
+    // to the end as a 16-bit stereo WAV, without printing the position. Songs that never
+    // end (or just run long) are cut off after max_seconds
+    pub fn render_to_wav<W: Write + Seek>(
+        &mut self,
+        mut writer: W,
+        max_seconds: u32,
+    ) -> io::Result<()> {
+        let max_frames = self.samplerate as u64 * max_seconds as u64;
+        let print_position = self.print_position;
+        self.print_position = false;
+
+        // Otherwise the first row would only start a tick in
+        if self.current_row == 65535 && !self.seek(0, 0) {
+            self.print_position = print_position;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the song has no orders to play",
+            ));
+        }
+
+        let start = writer.stream_position()?;
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(0)?; // Filled in at the end
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(16)?;
+        writer.write_u16::<LittleEndian>(1)?; // PCM
+        writer.write_u16::<LittleEndian>(2)?; // Channels
+        writer.write_u32::<LittleEndian>(self.samplerate)?;
+        writer.write_u32::<LittleEndian>(self.samplerate * 4)?; // Bytes per second
+        writer.write_u16::<LittleEndian>(4)?; // Bytes per frame
+        writer.write_u16::<LittleEndian>(16)?; // Bits per sample
+
+        writer.write_all(b"data")?;
+        writer.write_u32::<LittleEndian>(0)?; // Filled in at the end
+
+        let mut frames = 0u64;
+        let mut result = Ok(());
+        while result.is_ok() && !self.is_finished() && frames < max_frames {
+            let (left, right) = self.process();
+            let (left, right) = (self.output_16_bit(left), self.output_16_bit(right));
+            result = writer
+                .write_i16::<LittleEndian>(left)
+                .and_then(|_| writer.write_i16::<LittleEndian>(right));
+            frames += 1;
+        }
+        self.print_position = print_position;
+        result?;
+
+        let data_length = (frames * 4).min(u32::MAX as u64 - 36) as u32;
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(start + 4))?;
+        writer.write_u32::<LittleEndian>(data_length + 36)?;
+        writer.seek(SeekFrom::Start(start + 40))?;
+        writer.write_u32::<LittleEndian>(data_length)?;
+        writer.seek(SeekFrom::Start(end))?;
+
+        Ok(())
+    }
+
+    // Keeps the top 16 bits of an output sample. Dithering adds triangular noise of up
+    // to 1 LSB either way and rounds, so quiet parts fade into noise instead of steps
+    fn output_16_bit(&mut self, value: i32) -> i16 {
+        if !self.dither {
+            return (value >> 16) as i16;
+        }
+
+        let noise = (xorshift(&mut self.dither_state) >> 16) as i64
+            + (xorshift(&mut self.dither_state) >> 16) as i64
+            - 65536;
+        ((value as i64 + noise + 32768) >> 16).clamp(i16::MIN as i64, i16::MAX as i64) as i16
+    }
+
+    // Frames per tick at the current tempo
+    fn tick_length(&self) -> u32 {
+        ((self.samplerate as f32 * 2.5) / self.current_tempo as f32) as u32
+    }
+
+    // Both sides of a frame averaged, for centred channels that's what the player
+    // output before it was stereo
+    pub fn process_mono(&mut self) -> i32 {
+        let (left, right) = self.process();
+        ((left as i64 + right as i64) / 2) as i32
+    }
+
+    // Fills a buffer with interleaved left/right frames, a trailing odd sample is left alone
+    pub fn render(&mut self, out: &mut [i32]) {
+        for frame in out.chunks_exact_mut(2) {
+            (frame[0], frame[1]) = self.process();
+        }
+    }
+
+    pub fn render_mono(&mut self, out: &mut [i32]) {
+        for s in out.iter_mut() {
+            *s = self.process_mono();
+        }
+    }
+
+    fn lofi(&mut self, mut out: (i32, i32)) -> (i32, i32) {
+        // Sample rate reduction, sample and hold
+        if self.downsample > 1 {
+            if self.hold_counter == 0 {
+                self.held_frame = out;
+            }
+            out = self.held_frame;
+            self.hold_counter = (self.hold_counter + 1) % self.downsample;
+        }
+
+        // Bit depth reduction
+        if self.bitcrush > 0 && self.bitcrush < 32 {
+            let shift = 32 - self.bitcrush as u32;
+            out = ((out.0 >> shift) << shift, (out.1 >> shift) << shift);
+        }
+
+        out
+    }
+
+    fn process_tick(&mut self) {
+        let Some(row) = self.row() else {
+            return;
+        };
+
+        // At speed 1 the first tick is the only one, so continuous effects get their step there
+        if self.ticks_passed == 0 && self.current_speed > 1 {
+            return;
+        };
+
+        for (i, col) in row.iter().enumerate() {
+            let channel = &mut self.channels[i];
+
+            match col.vol {
+                VolEffect::VolSlideUp(value) => channel.vol_column_slide(value, true),
+                VolEffect::VolSlideDown(value) => channel.vol_column_slide(value, false),
+                // e0x/f0x are Exx/Fxx at 4x the step and share their memory
+                VolEffect::PortaDown(value) => {
+                    channel.porta_down(self.module.linear_freq_slides, value * 4)
+                }
+                VolEffect::PortaUp(value) => {
+                    channel.porta_up(self.module.linear_freq_slides, value * 4)
+                }
+                VolEffect::TonePorta(value) => channel.tone_portamento(
+                    self.module.linear_freq_slides,
+                    VOL_COLUMN_PORTA[value as usize],
+                ),
+                VolEffect::VibratoDepth(value) => {
+                    channel.vibrato(self.module.linear_freq_slides, value, false)
+                }
+                _ => {}
+            }
+
+            match col.effect {
                 Effect::PortaUp(value) => channel.porta_up(self.module.linear_freq_slides, value),
                 Effect::PortaDown(value) => {
                     channel.porta_down(self.module.linear_freq_slides, value)
                 }
                 Effect::TonePorta(value) => {
-                    channel.tone_portamento(col.note, self.module.linear_freq_slides, value)
+                    channel.tone_portamento(self.module.linear_freq_slides, value)
                 }
                 Effect::VolSlide(value) => channel.vol_slide(value),
+                Effect::VolSlideTonePorta(value) => {
+                    // The volume slide shares Dxy's memory, the portamento keeps its own
+                    channel.vol_slide(value);
+                    let speed = *channel.tone_porta_memory();
+                    channel.slide_to_note(self.module.linear_freq_slides, speed)
+                }
+                Effect::Vibrato(value) => {
+                    channel.vibrato(self.module.linear_freq_slides, value, false)
+                }
+                Effect::FineVibrato(value) => {
+                    channel.vibrato(self.module.linear_freq_slides, value, true)
+                }
+                Effect::VolSlideVibrato(value) => {
+                    // Like Lxy, the vibrato half carries on with the last Hxy/Uxy
+                    channel.vol_slide(value);
+                    channel.update_vibrato(self.module.linear_freq_slides)
+                }
+                Effect::Tremor(value) => channel.tremor(value),
+                Effect::Tremolo(value) => channel.tremolo(value),
+                Effect::Panbrello(value) => channel.panbrello(value),
                 Effect::Retrig(value) => channel.retrigger(value),
+                // SC0 is treated as SC1
+                Effect::NoteCut(ticks) if self.ticks_passed == ticks.max(1) => channel.note_cut(),
+                // Same for SD0, and a delay past the row's last tick never triggers.
+                // Repeats of the row from SEx don't trigger it again
+                Effect::NoteDelay(ticks)
+                    if self.ticks_passed == ticks.max(1) && self.pattern_delay_passed == 0 =>
+                {
+                    self.trigger_column(i, col)
+                }
                 _ => {}
             }
         }
@@ -428,17 +1656,17 @@ impl Player<'_> {
             return;
         };
 
-        let row = &self.module.patterns[self.current_pattern as usize][self.current_row as usize];
+        let row = self.row().into_iter().flatten();
         let mut pos_jump_enabled = false;
         let mut pos_jump_to = 0u8;
 
         let mut pat_break_enabled = false;
         let mut pat_break_to = 0u8;
 
-        for col in row.iter() {
+        let mut pat_loop_to = None;
+
+        for (i, col) in row.enumerate() {
             match col.effect {
-                Effect::SetSpeed(speed) => self.current_speed = speed,
-                Effect::SetTempo(tempo) => self.current_tempo = tempo,
                 Effect::PosJump(position) => {
                     pos_jump_enabled = true;
                     pos_jump_to = position
@@ -447,111 +1675,347 @@ impl Player<'_> {
                     pat_break_enabled = true;
                     pat_break_to = row
                 }
+                // Every channel keeps its own loop
+                Effect::PatLoopStart => self.channels[i].pattern_loop_row = self.current_row,
+                Effect::PatLoop(count) => {
+                    let channel = &mut self.channels[i];
+                    if channel.pattern_loop_count == 0 {
+                        channel.pattern_loop_count = count;
+                        pat_loop_to = Some(channel.pattern_loop_row);
+                    } else {
+                        channel.pattern_loop_count -= 1;
+                        if channel.pattern_loop_count != 0 {
+                            pat_loop_to = Some(channel.pattern_loop_row);
+                        } else {
+                            // Like IT, a finished loop starts again after itself
+                            channel.pattern_loop_row = self.current_row + 1;
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
         self.ticks_passed = 0;
-        if self.current_row == self.module.patterns[self.current_pattern as usize].len() as u16 {
+
+        let (start_order, start_row, end_order, end_row) = self.ab_loop;
+        if self.ab_loop_enabled && self.current_position == end_order && self.current_row == end_row
+        {
+            self.set_position(start_order);
+            self.current_row = start_row;
+            return;
+        }
+
+        if self.current_row as usize >= self.module.patterns[self.current_pattern as usize].len() {
             self.current_row = 0;
         } else {
+            // A loop jump takes priority over Bxx and Cxx on the same row
+            if let Some(row) = pat_loop_to {
+                self.current_row = row;
+                return;
+            }
+
             self.current_row += 1;
             if pos_jump_enabled {
                 self.current_row = 0;
-                self.current_position = pos_jump_to;
-                self.current_pattern = self.module.playlist[self.current_position as usize];
+                self.set_position(pos_jump_to);
             }
 
             if pat_break_enabled {
-                self.current_row = pat_break_to as u16;
-                self.current_position += 1;
-                self.current_pattern = self.module.playlist[self.current_position as usize];
-
-                if self.current_pattern == 255 {
-                    self.current_position = 0;
-                    self.current_pattern = self.module.playlist[self.current_position as usize];
-                }
+                self.set_position(self.current_position.saturating_add(1));
+                // Like IT, breaking past the end of the next pattern goes to its first row
+                self.current_row = if (pat_break_to as usize)
+                    < self.module.patterns[self.current_pattern as usize].len()
+                {
+                    pat_break_to as u16
+                } else {
+                    0
+                };
             }
         }
 
-        if self.current_row as usize == self.module.patterns[self.current_pattern as usize].len() {
+        if self.finished {
+            return;
+        }
+
+        if self.current_row as usize >= self.module.patterns[self.current_pattern as usize].len() {
             self.current_row = 0;
-            self.current_position += 1;
-            self.current_pattern = self.module.playlist[self.current_position as usize];
+            self.set_position(self.current_position.saturating_add(1));
+        };
+    }
 
-            if self.current_pattern == 255 {
-                // End of song marker
-                std::process::exit(0);
+    // Moves to an order, skipping +++ markers. Running into the end of song marker
+    // (or off the end of the playlist) either restarts the song or finishes it
+    fn set_position(&mut self, position: u8) {
+        match self.resolve_order(position as usize) {
+            Some(order) => {
+                self.current_position = order as u8;
+                self.current_pattern = self.module.playlist[order];
+                self.reset_pattern_loops();
             }
-        };
+            None if self.looping && position != 0 => self.set_position(0),
+            None => self.finished = true,
+        }
+    }
+
+    // Like IT, SB0 and SBx only loop within a pattern, a new one starts from scratch
+    fn reset_pattern_loops(&mut self) {
+        for c in self.channels.iter_mut() {
+            c.pattern_loop_row = 0;
+            c.pattern_loop_count = 0;
+        }
+    }
+
+    // First playable order from `order` on, None at the end of the song
+    fn resolve_order(&self, mut order: usize) -> Option<usize> {
+        while self.module.playlist.get(order) == Some(&254) {
+            order += 1;
+        }
+
+        match self.module.playlist.get(order) {
+            Some(&pattern) if (pattern as usize) < self.module.patterns.len() => Some(order),
+            _ => None,
+        }
+    }
+
+    // The row being played, None before the first one
+    fn row(&self) -> Option<&'a Row> {
+        self.module
+            .patterns
+            .get(self.current_pattern as usize)?
+            .get(self.current_row as usize)
     }
 
     fn play_row(&mut self) {
-        let row = &self.module.patterns[self.current_pattern as usize][self.current_row as usize];
+        let Some(row) = self.row() else {
+            return;
+        };
 
-        print!(
-            "Position {}, Pattern {}, Row {}\x1b[K\r",
-            self.current_position, self.current_pattern, self.current_row
-        );
-        stdout().flush().unwrap();
+        if self.print_position {
+            // Not being able to show it is no reason to stop playing
+            let mut out = stdout().lock();
+            let _ = write!(
+                out,
+                "Position {}, Pattern {}, Row {}\x1b[K\r",
+                self.current_position, self.current_pattern, self.current_row
+            );
+            let _ = out.flush();
+        }
+
+        self.pattern_delay = 0;
+        self.pattern_delay_passed = 0;
+        self.fine_pattern_delay = 0;
+
+        // Speed and tempo apply to the row they're on, so both are set before anything
+        // else on the row and before its ticks are scheduled. The rightmost channel wins,
+        // except for pattern delays where the first one counts
+        for col in row.iter() {
+            match col.effect {
+                // A00 leaves the speed alone in both IT and ST3
+                Effect::SetSpeed(speed) if speed != 0 => self.current_speed = speed,
+                Effect::SetTempo(tempo) => self.current_tempo = tempo,
+                Effect::PatDelay(rows) if self.pattern_delay == 0 => self.pattern_delay = rows,
+                Effect::FinePatternDelay(ticks) if self.fine_pattern_delay == 0 => {
+                    self.fine_pattern_delay = ticks
+                }
+                _ => {}
+            }
+        }
 
         for (i, col) in row.iter().enumerate() {
+            if let Effect::SetGlobalVol(volume) = col.effect {
+                self.current_global_volume = volume.min(128);
+            }
+
             let channel = &mut self.channels[i];
 
-            /* match col.effect {
+            match col.effect {
+                Effect::SetVibratoWaveform(waveform) => channel.vibrato.set_waveform(waveform),
+                Effect::SetTremoloWaveform(waveform) => channel.tremolo.set_waveform(waveform),
+                Effect::SetPanbrelloWaveform(waveform) => channel.panbrello.set_waveform(waveform),
                 _ => {}
-                //TODO effects
-            } */
+            }
 
-            match col.vol {
-                // TODO volume commands
-                VolEffect::None => {}
-                VolEffect::FineVolSlideUp(_) => {}
-                VolEffect::FineVolSlideDown(_) => {}
-                VolEffect::VolSlideUp(_) => {}
-                VolEffect::VolSlideDown(_) => {}
-                VolEffect::PortaDown(_) => {}
-                VolEffect::PortaUp(_) => {}
-                VolEffect::TonePorta(_) => {}
-                VolEffect::VibratoDepth(_) => {}
-                VolEffect::SetPan(_) => {}
-                VolEffect::Volume(volume) => channel.volume = volume as f32,
-            }
-
-            if col.instrument != 0 {
-                channel.current_sample_index = col.instrument - 1;
-
-                if matches!(col.vol, VolEffect::None) {
-                    channel.volume = self.module.samples[channel.current_sample_index as usize]
-                        .default_volume as f32
-                }
-            }
-
-            match col.note {
-                Note::None => {}
-                Note::On(note) => {
-                    if !matches!(col.effect, Effect::TonePorta(_))
-                        && !matches!(col.vol, VolEffect::TonePorta(_))
-                    {
-                        channel.playing = true;
-                        channel.position = match col.effect {
-                            Effect::SampleOffset(position) => {
-                                if position != 0 {
-                                    channel.offset_memory = position
-                                };
-                                channel.offset_memory as f64 * 256.0
-                            }
-                            _ => 0.0,
+            match col.effect {
+                Effect::Vibrato(_) | Effect::FineVibrato(_) | Effect::VolSlideVibrato(_) => {}
+                _ if matches!(col.vol, VolEffect::VibratoDepth(_)) => {}
+                _ => channel.vibrato_freq = 0.0,
+            }
+            if !matches!(col.effect, Effect::Tremolo(_)) {
+                channel.tremolo_volume = 0.0;
+            }
+            if !matches!(col.effect, Effect::Panbrello(_)) {
+                channel.panbrello_pan = 0;
+            }
+            if !matches!(col.effect, Effect::Tremor(_)) {
+                channel.tremor_mute = false;
+            }
+
+            // SDx holds back the whole note, instrument and volume until process_tick
+            if !matches!(col.effect, Effect::NoteDelay(_)) {
+                self.trigger_column(i, col);
+            }
+
+            let channel = &mut self.channels[i];
+            match col.effect {
+                Effect::SetPan(panning) => channel.panning = ((panning as u16 * 64 + 7) / 15) as u8,
+                Effect::FineSetPan(panning) => {
+                    channel.panning = ((panning as u16 + 2) / 4) as u8;
+                }
+                Effect::VolEnvOff => channel.volume_envelope_enabled = false,
+                Effect::VolEnvOn => channel.volume_envelope_enabled = true,
+                Effect::NNANoteCut => channel.nna = NewNoteAction::Cut,
+                Effect::NNANoteContinue => channel.nna = NewNoteAction::Continue,
+                Effect::NNANoteOff => channel.nna = NewNoteAction::Off,
+                Effect::NNANoteFade => channel.nna = NewNoteAction::Fade,
+                _ => {}
+            }
+
+            let past_action = match col.effect {
+                Effect::PastNoteCut => Some(NewNoteAction::Cut),
+                Effect::PastNoteOff => Some(NewNoteAction::Off),
+                Effect::PastNoteFade => Some(NewNoteAction::Fade),
+                _ => None,
+            };
+            if let Some(action) = past_action {
+                for (_, voice) in self
+                    .background_voices
+                    .iter_mut()
+                    .filter(|(c, _)| *c as usize == i)
+                {
+                    voice.apply_action(action);
+                }
+            }
+        }
+    }
+
+    // Before a new note starts on a channel, older notes of the same instrument are
+    // checked for duplicates, then the playing note moves to a background voice unless
+    // its NNA is to cut it. Only instrument mode has either
+    fn new_note_action(&mut self, i: usize, instrument: Option<u8>, key: u8, sample: u8) {
+        let module = self.module;
+        let Some(new) = instrument.and_then(|n| module.instruments.get(n as usize)) else {
+            return;
+        };
+
+        let is_duplicate = |voice: &Channel| {
+            voice.playing
+                && voice.current_instrument == instrument
+                && match new.duplicate_check {
+                    DuplicateCheck::None => false,
+                    DuplicateCheck::Note => voice.last_note == key,
+                    DuplicateCheck::Sample => voice.current_sample_index == sample,
+                    DuplicateCheck::Instrument => true,
+                }
+        };
+
+        for (_, voice) in self
+            .background_voices
+            .iter_mut()
+            .filter(|(c, _)| *c as usize == i)
+        {
+            if is_duplicate(voice) {
+                voice.apply_action(new.duplicate_check_action);
+            }
+        }
+
+        let channel = &mut self.channels[i];
+        if is_duplicate(channel) {
+            channel.apply_action(new.duplicate_check_action);
+        }
+
+        if channel.playing && !matches!(channel.nna, NewNoteAction::Cut) {
+            let mut voice = channel.clone();
+            voice.apply_action(voice.nna);
+            // The note carries on in the background, so there's nothing to fade out here
+            channel.last_output = 0.0;
+
+            make_room(&mut self.background_voices, MAX_BACKGROUND_VOICES);
+            self.background_voices.push((i as u8, voice));
+        }
+    }
+
+    // Applies a column's volume, instrument and note to its channel
+    fn trigger_column(&mut self, i: usize, col: &Column) {
+        let channel = &mut self.channels[i];
+
+        // Fine slides happen once here, the rest run every tick in process_tick
+        match col.vol {
+            VolEffect::FineVolSlideUp(value) => channel.vol_column_slide(value, true),
+            VolEffect::FineVolSlideDown(value) => channel.vol_column_slide(value, false),
+            VolEffect::SetPan(panning) => channel.panning = panning.min(64),
+            VolEffect::Volume(volume) => channel.volume = volume as f32,
+            _ => {}
+        }
+
+        // In instrument mode the instrument's note map picks the sample (1-based here)
+        // and the note it plays at, otherwise the column names the sample directly.
+        // Rows without a note go by the last key, last_note is already mapped
+        let key = match col.note {
+            Note::On(key) => key,
+            _ => channel.last_key,
+        };
+        let (instrument, note, sample) = match self.module.mode {
+            PlaybackMode::IT => {
+                let instrument = match col.instrument {
+                    0 => channel.current_instrument,
+                    i => Some(i - 1),
+                };
+                match map_note(self.module, instrument, key) {
+                    Some((note, sample)) => (instrument, note, sample),
+                    None => (None, key, 0),
+                }
+            }
+            _ => (None, key, col.instrument),
+        };
+
+        // An instrument without a note only resets the volume, the playing sample
+        // is only switched once a note actually triggers it. In instrument mode a
+        // note the instrument maps to no sample has nothing to play
+        let sample_index = if sample != 0 && sample as usize <= self.module.samples.len() {
+            if col.instrument != 0 && matches!(col.vol, VolEffect::None) {
+                channel.volume = self.module.samples[sample as usize - 1].default_volume as f32
+            }
+
+            Some(sample - 1)
+        } else if let PlaybackMode::IT = self.module.mode {
+            None
+        } else {
+            Some(channel.current_sample_index)
+        };
+
+        match (col.note, sample_index) {
+            (Note::None, _) | (Note::On(_), None) => {}
+            // Like IT, a disabled channel runs its effects but never plays its notes
+            (Note::On(_), _) if !self.module.channel_enabled[i] => {}
+            (Note::On(_), Some(sample_index)) => {
+                channel.last_key = key;
+
+                // Tone portamento only glides if there's something to glide from,
+                // a silent channel triggers the note as usual
+                let porta = matches!(
+                    col.effect,
+                    Effect::TonePorta(_) | Effect::VolSlideTonePorta(_)
+                ) || matches!(col.vol, VolEffect::TonePorta(_));
+
+                if porta && channel.playing {
+                    channel.last_note = note;
+                } else {
+                    self.new_note_action(i, instrument, note, sample_index);
+                    let channel = &mut self.channels[i];
+
+                    channel.note_on(instrument, note, sample_index);
+                    if let Effect::SampleOffset(position) = col.effect {
+                        if position != 0 {
+                            channel.offset_memory = position
                         };
-                        channel.freq = 2f32.powf((note as f32 - 60.0) / 12.0)
-                            * self.module.samples[channel.current_sample_index as usize]
-                                .base_frequency as f32;
+                        channel.position = channel.offset_memory as f64 * 256.0;
                     }
                 }
-                Note::Fade => {}
-                Note::Cut => channel.playing = false,
-                Note::Off => channel.playing = false,
             }
+            (Note::Fade, _) => channel.fading = true,
+            (Note::Cut, _) => channel.playing = false,
+            (Note::Off, _) => channel.note_off(),
         }
     }
 }
@@ -560,8 +2024,1465 @@ impl AudioCallback for Player<'_> {
     type Channel = i32;
 
     fn callback(&mut self, out: &mut [i32]) {
-        for s in out.iter_mut() {
-            *s = self.process();
+        self.render(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::module::{Envelope, Instrument, Pattern};
+
+    fn column(note: Note, effect: Effect) -> Column {
+        Column {
+            note,
+            instrument: 0,
+            vol: VolEffect::None,
+            effect,
+        }
+    }
+
+    fn empty_pattern(rows: usize) -> Pattern {
+        (0..rows)
+            .map(|_| vec![column(Note::None, Effect::None)])
+            .collect()
+    }
+
+    fn test_module(patterns: Vec<Pattern>) -> Module {
+        Module {
+            name: String::new(),
+            mode: PlaybackMode::ITSample,
+            tracker: String::new(),
+            linear_freq_slides: true,
+            compatible_gxx: false,
+            initial_tempo: 125,
+            initial_speed: 6,
+            initial_global_volume: 128,
+            channel_enabled: [true; 64],
+            initial_panning: [32; 64],
+            samples: vec![Sample {
+                base_frequency: 8363,
+                loop_type: LoopType::None,
+                loop_start: 0,
+                loop_end: 0,
+                default_volume: 64,
+                global_volume: 64,
+                audio: (0..8).map(|x| x * 1000).collect(),
+            }],
+            instruments: vec![],
+            playlist: (0..patterns.len() as u8).chain([255]).collect(),
+            patterns,
+        }
+    }
+
+    // An instrument with nothing but a note map, the tests fill in the rest
+    fn test_instrument(sample_map: Vec<(u8, u8)>) -> Instrument {
+        Instrument {
+            fadeout: 0,
+            new_note_action: NewNoteAction::Cut,
+            duplicate_check: DuplicateCheck::None,
+            duplicate_check_action: NewNoteAction::Cut,
+            sample_map,
+            volume_envelope: Envelope::default(),
+        }
+    }
+
+    // Keeps the position off stdout, libtest can't capture it
+    fn test_player(module: &Module, samplerate: u32) -> Player<'_> {
+        let mut player = Player::from_module(module, samplerate);
+        player.print_position = false;
+        player
+    }
+
+    // Order and row of the next `count` rows played
+    fn rows_played(player: &mut Player, count: usize) -> Vec<(u8, u16)> {
+        (0..count)
+            .map(|_| {
+                player.advance_row();
+                player.play_row();
+                player.position()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ab_loop() {
+        let module = test_module(vec![empty_pattern(4), empty_pattern(4)]);
+        let mut player = test_player(&module, 48000);
+
+        assert!(player.set_ab_loop(0, 2, 1, 0));
+        assert_eq!(
+            rows_played(&mut player, 7),
+            [(0, 0), (0, 1), (0, 2), (0, 3), (1, 0), (0, 2), (0, 3)]
+        );
+
+        // Rows past the end of the pattern are rejected, the old loop stays
+        assert!(!player.set_ab_loop(0, 10, 1, 0));
+        assert!(!player.set_ab_loop(0, 0, 1, 4));
+        assert!(!player.set_ab_loop(2, 0, 2, 0));
+        assert_eq!(rows_played(&mut player, 3), [(1, 0), (0, 2), (0, 3)]);
+    }
+
+    #[test]
+    fn preview_uses_note_map() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.mode = PlaybackMode::IT;
+        module.samples.push(module.samples[0].clone());
+        module.instruments.push(Instrument {
+            fadeout: 64,
+            new_note_action: NewNoteAction::Cut,
+            duplicate_check: DuplicateCheck::None,
+            duplicate_check_action: NewNoteAction::Cut,
+            // An octave up on the second sample
+            sample_map: (0..120).map(|note| (note + 12, 2)).collect(),
+            volume_envelope: Envelope {
+                enabled: true,
+                sustain_enabled: true,
+                nodes: vec![(0, 64), (4, 32)],
+                ..Default::default()
+            },
+        });
+        let mut player = test_player(&module, 48000);
+
+        for key in [48, 52, 55] {
+            player.preview_note_on(key, 0);
+        }
+        assert_eq!(player.preview_voices.len(), 3);
+        let (_, voice) = &player.preview_voices[0];
+        assert_eq!(voice.current_sample_index, 1);
+        assert_eq!(voice.last_note, 60);
+        assert_eq!(voice.freq, note_frequency(60, 8363));
+
+        player.preview_note_off(52);
+        let released: Vec<bool> = player
+            .preview_voices
+            .iter()
+            .map(|(_, v)| v.released)
+            .collect();
+        assert_eq!(released, [false, true, false]);
+    }
+
+    #[test]
+    fn channel_state_is_as_heard() {
+        let mut module = test_module(vec![vec![vec![Column {
+            note: Note::On(60),
+            instrument: 1,
+            vol: VolEffect::Volume(32),
+            effect: Effect::FineSetPan(255),
+        }]]]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        let mut player = test_player(&module, 48000);
+        player.set_global_volume(64);
+        player.seek(0, 0);
+
+        // Past the 5ms ramp
+        for _ in 0..480 {
+            player.process();
         }
+        let state = player.channel_state(0).unwrap();
+        assert_eq!(state.volume, 16.0);
+        assert_eq!(state.pan, 64);
+    }
+
+    #[test]
+    fn preview_after_song_end() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        let mut player = test_player(&module, 48000);
+        player.seek(0, 0);
+        while !player.is_finished() {
+            player.process();
+        }
+
+        player.preview_note_on(60, 0);
+        assert!((0..480).any(|_| player.process() != (0, 0)));
+    }
+
+    #[test]
+    fn pattern_loop() {
+        let mut pattern = empty_pattern(4);
+        pattern[1][0].effect = Effect::PatLoopStart;
+        pattern[3][0].effect = Effect::PatLoop(3);
+        let module = test_module(vec![pattern]);
+        let mut player = test_player(&module, 48000);
+
+        let rows: Vec<u16> = rows_played(&mut player, 13)
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect();
+        assert_eq!(rows, [0, 1, 2, 3, 1, 2, 3, 1, 2, 3, 1, 2, 3]);
+
+        player.advance_row();
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn song_tempo_overrides_initial() {
+        let mut pattern = empty_pattern(2);
+        pattern[0][0].effect = Effect::SetTempo(150);
+        let module = test_module(vec![pattern]);
+        let mut player = test_player(&module, 48000);
+
+        player.set_tempo(90);
+        player.set_speed(3);
+        player.seek(0, 0);
+        assert_eq!((player.tempo(), player.speed()), (150, 3));
+
+        // Without Txx on the first row the overrides decide how long it lasts, 3 ticks
+        // at 90 rather than 6 at 125
+        let mut pattern = empty_pattern(2);
+        pattern[1][0].effect = Effect::SetTempo(150);
+        let module = test_module(vec![pattern]);
+        let mut player = test_player(&module, 48000);
+
+        player.set_tempo(90);
+        player.set_speed(3);
+        player.seek(0, 0);
+        let tick_length = player.tick_length();
+        let frames = (1..).find(|_| {
+            player.process();
+            player.position() != (0, 0)
+        });
+        assert_eq!(frames, Some(3 * (tick_length + 1)));
+        assert_eq!((player.tempo(), player.speed()), (150, 3));
+    }
+
+    #[test]
+    fn unmapped_note_plays_nothing() {
+        let note = |key| Column {
+            note: Note::On(key),
+            instrument: 1,
+            vol: VolEffect::None,
+            effect: Effect::None,
+        };
+        let mut module = test_module(vec![vec![vec![note(48)], vec![note(60)]]]);
+        module.mode = PlaybackMode::IT;
+        module.instruments.push(Instrument {
+            fadeout: 0,
+            new_note_action: NewNoteAction::Cut,
+            duplicate_check: DuplicateCheck::None,
+            duplicate_check_action: NewNoteAction::Cut,
+            // Only the lower half of the keyboard has a sample
+            sample_map: (0..120).map(|key| (key, (key < 60) as u8)).collect(),
+            volume_envelope: Envelope::default(),
+        });
+        let mut player = test_player(&module, 48000);
+
+        player.seek(0, 0);
+        assert!(player.channels[0].playing);
+        assert_eq!(player.channels[0].last_note, 48);
+
+        // The note on the second row is ignored, the first one carries on
+        rows_played(&mut player, 1);
+        assert!(player.channels[0].playing);
+        assert_eq!(player.channels[0].last_note, 48);
+    }
+
+    #[test]
+    fn preview_while_paused() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        let mut player = test_player(&module, 48000);
+        player.seek(0, 0);
+        player.set_paused(true);
+
+        player.preview_note_on(60, 0);
+        assert!((0..480).any(|_| player.process() != (0, 0)));
+        assert_eq!(player.elapsed_samples(), 0);
+    }
+
+    #[test]
+    fn retrigger_volume_stays_in_range() {
+        let module = test_module(vec![empty_pattern(1)]);
+
+        // retrigger_ticks is set so every call actually retriggers
+        let mut quiet = Channel::new(&module);
+        quiet.volume = 0.0;
+        quiet.retrigger_ticks = 1;
+        quiet.retrigger(0x91);
+        assert_eq!(quiet.volume, 1.0);
+
+        let mut loud = Channel::new(&module);
+        loud.volume = 64.0;
+        loud.retrigger_ticks = 2;
+        loud.retrigger(0x12);
+        assert_eq!(loud.volume, 63.0);
+
+        // Slides keep going until they hit the ends, then stay there
+        for _ in 0..100 {
+            quiet.retrigger_ticks = 1;
+            quiet.retrigger(0xF1);
+            loud.retrigger_ticks = 1;
+            loud.retrigger(0x51);
+        }
+        assert_eq!((quiet.volume, loud.volume), (64.0, 0.0));
+    }
+
+    #[test]
+    fn xm_retrigger_memory_per_nibble() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.mode = PlaybackMode::XM;
+        let mut channel = Channel::new(&module);
+        channel.volume = 32.0;
+
+        channel.retrigger_ticks = 1;
+        channel.retrigger(0x91);
+        assert_eq!(channel.volume, 33.0);
+
+        // Only the interval is new, the +1 carries over
+        channel.retrigger_ticks = 3;
+        channel.retrigger(0x03);
+        assert_eq!(channel.volume, 34.0);
+        assert_eq!(channel.retrigger_memory, 0x93);
+
+        // And the other way around, the interval of 3 is kept
+        channel.retrigger_ticks = 2;
+        channel.retrigger(0x50);
+        assert_eq!(channel.volume, 34.0);
+        channel.retrigger(0x50);
+        assert_eq!(channel.volume, 18.0);
+    }
+
+    #[test]
+    fn render_to_wav_starts_on_first_row() {
+        let mut module = test_module(vec![vec![vec![column(Note::On(60), Effect::None)]]]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        module.samples[0].audio = vec![16000; 8];
+
+        let mut expected = test_player(&module, 1000);
+        expected.seek(0, 0);
+        let expected: Vec<i16> = (0..20)
+            .flat_map(|_| {
+                let (left, right) = expected.process();
+                [(left >> 16) as i16, (right >> 16) as i16]
+            })
+            .collect();
+
+        let mut player = Player::from_module(&module, 1000);
+        let mut wav = std::io::Cursor::new(Vec::new());
+        player.render_to_wav(&mut wav, 1).unwrap();
+        assert!(player.print_position);
+
+        let wav = wav.into_inner();
+        let word = |at: usize| u32::from_le_bytes(wav[at..at + 4].try_into().unwrap()) as usize;
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(word(4), wav.len() - 8);
+        assert_eq!(word(40), wav.len() - 44);
+        assert!(wav.len() - 44 <= 1000 * 4);
+
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .take(40)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, expected);
+        assert!(samples.iter().any(|&s| s != 0));
+    }
+
+    // Positions after each of `count` steps of 11 samples, far more than the 8-sample buffer
+    fn positions(module: &Module, count: usize) -> Vec<Option<f64>> {
+        let mut channel = Channel::new(module);
+        channel.playing = true;
+        channel.freq = 11.0;
+        (0..count)
+            .map(|_| {
+                channel.process(1, Interpolation::None, false, 128);
+                channel.playing.then_some(channel.position)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unlooped_sample_stops_at_end() {
+        let module = test_module(vec![empty_pattern(1)]);
+        assert_eq!(positions(&module, 2), [None, None]);
+    }
+
+    #[test]
+    fn forward_loop_wraps_long_steps() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_start = 2;
+        module.samples[0].loop_end = 6;
+        assert_eq!(
+            positions(&module, 8),
+            [3.0, 2.0, 5.0, 4.0, 3.0, 2.0, 5.0, 4.0].map(Some)
+        );
+    }
+
+    #[test]
+    fn ping_pong_loop_folds_long_steps() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.samples[0].loop_type = LoopType::PingPong;
+        module.samples[0].loop_start = 2;
+        module.samples[0].loop_end = 6;
+        // Bounces between 2 and 5, the last sample in the loop
+        assert_eq!(
+            positions(&module, 7),
+            [5.0, 4.0, 3.0, 2.0, 3.0, 4.0, 5.0].map(Some)
+        );
+    }
+
+    #[test]
+    fn porta_targets_mapped_note() {
+        let note = |key, effect| Column {
+            note: Note::On(key),
+            instrument: 1,
+            vol: VolEffect::None,
+            effect,
+        };
+        let mut module = test_module(vec![vec![
+            vec![note(48, Effect::None)],
+            vec![note(50, Effect::TonePorta(0xFF))],
+        ]]);
+        module.mode = PlaybackMode::IT;
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        // An octave up
+        module
+            .instruments
+            .push(test_instrument((0..120).map(|key| (key + 12, 1)).collect()));
+        let mut player = test_player(&module, 48000);
+
+        player.seek(0, 0);
+        rows_played(&mut player, 1);
+        player.ticks_passed = 1;
+        player.process_tick();
+        assert_eq!(player.channels[0].last_note, 62);
+        assert_eq!(player.channels[0].freq, note_frequency(62, 8363));
+    }
+
+    #[test]
+    fn instrument_row_maps_last_key_once() {
+        let mut module = test_module(vec![vec![
+            vec![Column {
+                note: Note::On(48),
+                instrument: 1,
+                vol: VolEffect::Volume(32),
+                effect: Effect::None,
+            }],
+            vec![Column {
+                note: Note::None,
+                instrument: 1,
+                vol: VolEffect::None,
+                effect: Effect::None,
+            }],
+        ]]);
+        module.mode = PlaybackMode::IT;
+        module.samples.push(Sample {
+            default_volume: 16,
+            ..module.samples[0].clone()
+        });
+        // An octave up, on the second sample from C-5
+        module.instruments.push(test_instrument(
+            (0..120)
+                .map(|key| (key + 12, 1 + (key >= 60) as u8))
+                .collect(),
+        ));
+        let mut player = test_player(&module, 48000);
+
+        player.seek(0, 0);
+        assert_eq!(player.channels[0].volume, 32.0);
+
+        // 48 is still on the first sample, only 60 (its mapped note) isn't
+        rows_played(&mut player, 1);
+        assert_eq!(player.channels[0].volume, 64.0);
+    }
+
+    fn envelope_module(fadeout: u16, volume_envelope: Envelope) -> Module {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.mode = PlaybackMode::IT;
+        module.instruments.push(Instrument {
+            fadeout,
+            volume_envelope,
+            ..test_instrument((0..120).map(|key| (key, 1)).collect())
+        });
+        module
+    }
+
+    // Envelope volume after each of `ticks` ticks, None once the note has ended
+    fn envelope_ticks(channel: &mut Channel, ticks: usize) -> Vec<Option<f32>> {
+        (0..ticks)
+            .map(|_| {
+                channel.update_envelope();
+                channel
+                    .playing
+                    .then_some(channel.envelope_volume * channel.fade_volume as f32 / 1024.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn envelope_sustain_and_release() {
+        let module = envelope_module(
+            0,
+            Envelope {
+                enabled: true,
+                sustain_enabled: true,
+                sustain_start: 1,
+                sustain_end: 1,
+                nodes: vec![(0, 64), (4, 32), (6, 0)],
+                ..Default::default()
+            },
+        );
+        let mut channel = Channel::new(&module);
+        channel.note_on(Some(0), 60, 0);
+
+        // Holds on the sustain node for as long as the note is held
+        assert_eq!(
+            envelope_ticks(&mut channel, 8),
+            [64.0, 56.0, 48.0, 40.0, 32.0, 32.0, 32.0, 32.0].map(Some)
+        );
+
+        // Note off lets go of it, and the note ends with the envelope at 0
+        channel.note_off();
+        assert!(!channel.fading);
+        assert_eq!(
+            envelope_ticks(&mut channel, 4),
+            [Some(32.0), Some(16.0), None, None]
+        );
+    }
+
+    #[test]
+    fn envelope_fadeout() {
+        let module = envelope_module(
+            256,
+            Envelope {
+                enabled: true,
+                sustain_enabled: true,
+                sustain_start: 1,
+                sustain_end: 1,
+                nodes: vec![(0, 64), (2, 32)],
+                ..Default::default()
+            },
+        );
+        let mut channel = Channel::new(&module);
+        channel.note_on(Some(0), 60, 0);
+        envelope_ticks(&mut channel, 4);
+
+        // The fadeout only starts once the released envelope reaches its end
+        channel.note_off();
+        assert_eq!(
+            envelope_ticks(&mut channel, 4),
+            [Some(24.0), Some(16.0), Some(8.0), None]
+        );
+
+        // Note fade (and instruments without an envelope) fade out right away
+        let module = envelope_module(512, Envelope::default());
+        let mut channel = Channel::new(&module);
+        channel.note_on(Some(0), 60, 0);
+        channel.note_off();
+        assert_eq!(envelope_ticks(&mut channel, 2), [Some(32.0), None]);
+    }
+
+    #[test]
+    fn volume_envelope_toggles() {
+        let note = Column {
+            note: Note::On(60),
+            instrument: 1,
+            vol: VolEffect::None,
+            effect: Effect::None,
+        };
+        let mut module = envelope_module(
+            0,
+            Envelope {
+                enabled: true,
+                nodes: vec![(0, 64), (64, 0)],
+                ..Default::default()
+            },
+        );
+        module.patterns = vec![vec![
+            vec![note],
+            vec![column(Note::None, Effect::VolEnvOff)],
+            vec![column(Note::None, Effect::VolEnvOn)],
+        ]];
+        let mut player = test_player(&module, 48000);
+
+        player.seek(0, 0);
+        assert_eq!(
+            envelope_ticks(&mut player.channels[0], 2),
+            [64.0, 63.0].map(Some)
+        );
+
+        // S77 plays the note at full volume and holds the envelope where it is,
+        // S78 carries on from there
+        rows_played(&mut player, 1);
+        assert_eq!(
+            envelope_ticks(&mut player.channels[0], 2),
+            [64.0, 64.0].map(Some)
+        );
+        rows_played(&mut player, 1);
+        assert_eq!(
+            envelope_ticks(&mut player.channels[0], 2),
+            [62.0, 61.0].map(Some)
+        );
+    }
+
+    #[test]
+    fn pattern_loop_resets_on_new_pattern() {
+        let mut first = empty_pattern(8);
+        first[6][0].effect = Effect::PatLoopStart;
+        let mut second = empty_pattern(4);
+        second[3][0].effect = Effect::PatLoop(1);
+        let module = test_module(vec![first, second]);
+        let mut player = test_player(&module, 48000);
+
+        // The loop in the second pattern goes back to its own first row, not row 6
+        let rows = rows_played(&mut player, 16);
+        assert_eq!(
+            rows[8..],
+            [
+                (1, 0),
+                (1, 1),
+                (1, 2),
+                (1, 3),
+                (1, 0),
+                (1, 1),
+                (1, 2),
+                (1, 3)
+            ]
+        );
+
+        player.advance_row();
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn disabled_channel_runs_effects_silently() {
+        let mut pattern = empty_pattern(2);
+        for row in pattern.iter_mut() {
+            row.push(column(Note::None, Effect::None));
+        }
+        pattern[0][1] = Column {
+            note: Note::On(60),
+            instrument: 1,
+            vol: VolEffect::Volume(32),
+            effect: Effect::VolSlide(0x02),
+        };
+        pattern[1][1].effect = Effect::PatLoop(1);
+        let mut module = test_module(vec![pattern]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        module.channel_enabled[1] = false;
+        let mut player = test_player(&module, 48000);
+
+        player.seek(0, 0);
+        while player.position() == (0, 0) {
+            assert_eq!(player.process(), (0, 0));
+        }
+        assert!(!player.channels[1].playing);
+
+        // The volume column, the slide over the row's 5 ticks and the loop all
+        // still happen
+        assert_eq!(player.channels[1].volume, 22.0);
+        player.advance_row();
+        assert_eq!(player.position(), (0, 0));
+    }
+
+    // A pattern with one column per row on channel 0. The speed is 2, so every row
+    // gets a single tick of effects after its first
+    fn effect_module(mode: PlaybackMode, compatible_gxx: bool, columns: Vec<Column>) -> Module {
+        let mut module = test_module(vec![columns.into_iter().map(|c| vec![c]).collect()]);
+        module.mode = mode;
+        module.compatible_gxx = compatible_gxx;
+        module.initial_speed = 2;
+        module
+    }
+
+    // Channel 0 after each row and its effect tick
+    fn channel_per_row(module: &Module) -> Vec<Channel<'_>> {
+        let mut player = test_player(module, 48000);
+        (0..module.patterns[0].len())
+            .map(|_| {
+                player.advance_row();
+                player.play_row();
+                player.ticks_passed = 1;
+                player.process_tick();
+                player.channels[0].clone()
+            })
+            .collect()
+    }
+
+    fn note(key: u8, vol: VolEffect, effect: Effect) -> Column {
+        Column {
+            note: Note::On(key),
+            instrument: 1,
+            vol,
+            effect,
+        }
+    }
+
+    #[test]
+    fn effect_memory_matrix() {
+        for (s3m, compatible_gxx) in [(true, false), (false, false), (false, true)] {
+            let config = format!("S3M {}, Compatible Gxx {}", s3m, compatible_gxx);
+            let module = |columns| {
+                let mode = if s3m {
+                    PlaybackMode::S3M
+                } else {
+                    PlaybackMode::ITSample
+                };
+                effect_module(mode, compatible_gxx, columns)
+            };
+
+            // G00 picks up E05's speed, except in IT with Compatible Gxx where it
+            // has a memory of its own (still empty here)
+            let patterned = module(vec![
+                note(60, VolEffect::None, Effect::None),
+                column(Note::None, Effect::PortaDown(5)),
+                note(62, VolEffect::None, Effect::TonePorta(0)),
+            ]);
+            let rows = channel_per_row(&patterned);
+            let expected = if compatible_gxx {
+                rows[1].freq
+            } else {
+                rows[1].freq * 2f32.powf(4.0 * 5.0 / 768.0)
+            };
+            assert!((rows[2].freq - expected).abs() < 0.01, "G00, {}", config);
+
+            // Lxy's volume slide goes on from Dxy, and L00 from Lxy
+            let patterned = module(vec![
+                note(60, VolEffect::None, Effect::None),
+                column(Note::None, Effect::VolSlide(0x04)),
+                column(Note::None, Effect::VolSlideTonePorta(0x02)),
+                column(Note::None, Effect::VolSlideTonePorta(0)),
+            ]);
+            let rows = channel_per_row(&patterned);
+            let volumes: Vec<f32> = rows.iter().map(|c| c.volume).collect();
+            assert_eq!(volumes, [64.0, 60.0, 58.0, 56.0], "L00, {}", config);
+
+            // Q00 retriggers with Q90's +1 volume
+            let patterned = module(vec![
+                note(60, VolEffect::Volume(32), Effect::None),
+                column(Note::None, Effect::Retrig(0x90)),
+                column(Note::None, Effect::Retrig(0)),
+            ]);
+            let rows = channel_per_row(&patterned);
+            let volumes: Vec<f32> = rows.iter().map(|c| c.volume).collect();
+            assert_eq!(volumes, [32.0, 33.0, 34.0], "Q00, {}", config);
+
+            // O00 starts the note at O02's offset again
+            let patterned = module(vec![
+                note(60, VolEffect::None, Effect::SampleOffset(2)),
+                note(60, VolEffect::None, Effect::SampleOffset(0)),
+            ]);
+            let rows = channel_per_row(&patterned);
+            let positions: Vec<f64> = rows.iter().map(|c| c.position).collect();
+            assert_eq!(positions, [512.0, 512.0], "O00, {}", config);
+        }
+    }
+
+    #[test]
+    fn ping_pong_taps_follow_playhead() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.samples[0].loop_type = LoopType::PingPong;
+        module.samples[0].loop_start = 2;
+        module.samples[0].loop_end = 6;
+        // From the last sample the taps bounce like the positions in ping_pong_loop_folds_long_steps
+        let taps: Vec<f32> = (5..10).map(|i| tap(&module.samples[0], i)).collect();
+        assert_eq!(taps, [5000.0, 4000.0, 3000.0, 2000.0, 3000.0]);
+    }
+
+    #[test]
+    fn interpolation_smooths_looped_sine() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        let sample = &mut module.samples[0];
+        sample.audio = (0..64)
+            .map(|i| ((i as f32 / 64.0 * std::f32::consts::TAU).sin() * 16000.0) as i16)
+            .collect();
+        sample.loop_type = LoopType::Forward;
+        sample.loop_start = 0;
+        sample.loop_end = 64;
+
+        // Largest jump between neighbouring outputs when stepping 0.3 samples at a time,
+        // across the loop point several times
+        let max_step = |interpolation: Interpolation| {
+            let output: Vec<f32> = (0..1000)
+                .map(|i| match interpolation {
+                    Interpolation::None => tap(&module.samples[0], (i as f64 * 0.3) as i64),
+                    Interpolation::Linear => linear(&module.samples[0], i as f64 * 0.3),
+                    _ => windowed_sinc(&module.samples[0], i as f64 * 0.3, 32, false),
+                })
+                .collect();
+            output
+                .windows(2)
+                .map(|w| (w[1] - w[0]).abs())
+                .fold(0.0, f32::max)
+        };
+
+        let none = max_step(Interpolation::None);
+        assert!(max_step(Interpolation::Linear) < none * 0.5);
+        assert!(max_step(Interpolation::Sinc32) < none * 0.5);
+    }
+
+    // Plays every row of a one-channel IT module with a single instrument, then returns
+    // (key, playing, released, fading) of each background voice
+    fn background_after(
+        instrument: Instrument,
+        columns: Vec<Column>,
+    ) -> Vec<(u8, bool, bool, bool)> {
+        let mut module = envelope_module(0, Envelope::default());
+        module.instruments[0] = instrument;
+        module.patterns[0] = columns.into_iter().map(|c| vec![c]).collect();
+        let mut player = test_player(&module, 48000);
+        rows_played(&mut player, module.patterns[0].len());
+        player
+            .background_voices
+            .iter()
+            .map(|(_, c)| (c.last_note, c.playing, c.released, c.fading))
+            .collect()
+    }
+
+    #[test]
+    fn new_note_actions() {
+        let instrument = |new_note_action| Instrument {
+            new_note_action,
+            ..test_instrument((0..120).map(|key| (key, 1)).collect())
+        };
+        let notes = || {
+            vec![
+                note(60, VolEffect::None, Effect::None),
+                note(62, VolEffect::None, Effect::None),
+            ]
+        };
+
+        assert_eq!(
+            background_after(instrument(NewNoteAction::Cut), notes()),
+            []
+        );
+        assert_eq!(
+            background_after(instrument(NewNoteAction::Continue), notes()),
+            [(60, true, false, false)]
+        );
+        // Without a volume envelope a released note fades right away
+        assert_eq!(
+            background_after(instrument(NewNoteAction::Off), notes()),
+            [(60, true, true, true)]
+        );
+        assert_eq!(
+            background_after(instrument(NewNoteAction::Fade), notes()),
+            [(60, true, false, true)]
+        );
+
+        // S74 overrides the instrument's Cut for the note on its row, S70 cuts it later
+        let columns = vec![
+            note(60, VolEffect::None, Effect::NNANoteContinue),
+            note(62, VolEffect::None, Effect::None),
+        ];
+        assert_eq!(
+            background_after(instrument(NewNoteAction::Cut), columns),
+            [(60, true, false, false)]
+        );
+        let mut columns = notes();
+        columns.push(column(Note::None, Effect::PastNoteCut));
+        assert_eq!(
+            background_after(instrument(NewNoteAction::Continue), columns),
+            [(60, false, false, false)]
+        );
+    }
+
+    #[test]
+    fn duplicate_check_cuts() {
+        let instrument = Instrument {
+            new_note_action: NewNoteAction::Continue,
+            duplicate_check: DuplicateCheck::Note,
+            duplicate_check_action: NewNoteAction::Cut,
+            ..test_instrument((0..120).map(|key| (key, 1)).collect())
+        };
+        // The second 60 cuts the first, in the background, before 62 moves there too
+        let columns = vec![
+            note(60, VolEffect::None, Effect::None),
+            note(62, VolEffect::None, Effect::None),
+            note(60, VolEffect::None, Effect::None),
+        ];
+        assert_eq!(
+            background_after(instrument.clone(), columns),
+            [(60, false, false, false), (62, true, false, false)]
+        );
+
+        // On the channel itself the cut note never reaches the background
+        let columns = vec![
+            note(60, VolEffect::None, Effect::None),
+            note(60, VolEffect::None, Effect::None),
+        ];
+        assert_eq!(background_after(instrument, columns), []);
+    }
+
+    #[test]
+    fn full_voice_pool_drops_quietest() {
+        let module = test_module(vec![empty_pattern(1)]);
+        let voice = |key, playing, ramp_gain| {
+            let mut channel = Channel::new(&module);
+            channel.playing = playing;
+            channel.ramp_gain = ramp_gain;
+            (key, channel)
+        };
+        let keys = |voices: &Vec<(u8, Channel)>| voices.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+        let mut voices = vec![
+            voice(0, true, 0.5),
+            voice(1, true, 0.1),
+            voice(2, true, 0.8),
+        ];
+        make_room(&mut voices, 4);
+        assert_eq!(keys(&voices), [0, 1, 2]);
+        make_room(&mut voices, 3);
+        assert_eq!(keys(&voices), [0, 2]);
+
+        // A voice ramping out goes first, whatever its last gain
+        let mut voices = vec![
+            voice(0, true, 0.5),
+            voice(1, false, 1.0),
+            voice(2, true, 0.8),
+        ];
+        make_room(&mut voices, 3);
+        assert_eq!(keys(&voices), [0, 2]);
+    }
+
+    #[test]
+    fn preview_voices_are_capped() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_start = 0;
+        module.samples[0].loop_end = 8;
+        let mut player = test_player(&module, 48000);
+
+        // Looped notes that are never released would otherwise pile up forever
+        for i in 0..1000 {
+            player.preview_note_on((i % 120) as u8, 0);
+            player.process();
+        }
+        assert_eq!(player.preview_voices.len(), 64);
+    }
+
+    #[test]
+    fn panning() {
+        assert_eq!(pan(1000, 0), (1000, 0));
+        assert_eq!(pan(1000, 16), (1000, 500));
+        assert_eq!(pan(1000, 32), (1000, 1000));
+        assert_eq!(pan(1000, 48), (500, 1000));
+        assert_eq!(pan(1000, 64), (0, 1000));
+
+        // S8x and Xxx are scaled to 0..=64, the volume column already is
+        let mut module = effect_module(
+            PlaybackMode::ITSample,
+            false,
+            vec![
+                note(60, VolEffect::None, Effect::SetPan(0)),
+                column(Note::None, Effect::SetPan(0xF)),
+                column(Note::None, Effect::FineSetPan(0)),
+                column(Note::None, Effect::FineSetPan(0xFF)),
+                column(Note::None, Effect::FineSetPan(0x80)),
+                note(60, VolEffect::SetPan(32), Effect::None),
+                note(60, VolEffect::None, Effect::SetPan(0)),
+            ],
+        );
+        let panning: Vec<u8> = channel_per_row(&module).iter().map(|c| c.panning).collect();
+        assert_eq!(panning, [0, 64, 0, 64, 32, 32, 0]);
+
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        module.samples[0].audio = vec![16000; 8];
+
+        // Hard left leaves the right side silent
+        let mut player = test_player(&module, 48000);
+        player.seek(0, 0);
+        let frames: Vec<(i32, i32)> = (0..1000).map(|_| player.process()).collect();
+        assert!(frames.iter().any(|&(left, _)| left != 0));
+        assert!(frames.iter().all(|&(_, right)| right == 0));
+
+        // Centred, each side is as loud as the mono output was before stereo
+        let mut player = test_player(&module, 48000);
+        player.seek(0, 5);
+        let frames: Vec<(i32, i32)> = (0..1000).map(|_| player.process()).collect();
+        assert!(frames.iter().any(|&(left, _)| left != 0));
+        assert!(frames.iter().all(|&(left, right)| left == right));
+    }
+
+    #[test]
+    fn replaced_sample_plays() {
+        let mut module = test_module(vec![vec![vec![column(Note::On(60), Effect::None)]]]);
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        module.replace_sample(0, vec![-16000; 64], None, false);
+        module.samples[0].loop_end = 64;
+
+        // Built after the replacement, so the note plays the new audio
+        let mut player = test_player(&module, 48000);
+        player.seek(0, 0);
+        let frames: Vec<(i32, i32)> = (0..1000).map(|_| player.process()).collect();
+        assert!(frames.iter().any(|&(left, _)| left != 0));
+        assert!(frames.iter().all(|&(left, _)| left <= 0));
+    }
+
+    #[test]
+    fn pitch_conversions() {
+        assert_eq!(note_frequency(60, 8363), 8363.0);
+        assert_eq!(note_frequency(72, 8363), 16726.0);
+        assert_eq!(note_frequency(48, 8363), 4181.5);
+
+        // C-5 at 8363 Hz is Amiga period 428
+        assert_eq!(period(8363.0).round(), 428.0);
+        for p in [113.0, 428.0, 856.0, 1712.0] {
+            assert!((period(freq_from_period(p)) - p).abs() < 0.01);
+        }
+        let freq = freq_from_period(period(8363.0).round());
+        assert!((freq - 8363.0).abs() < 8363.0 * 0.001);
+    }
+
+    #[test]
+    fn dither() {
+        let module = test_module(vec![empty_pattern(1)]);
+        let mut player = test_player(&module, 48000);
+
+        // A quarter of a 16-bit step is lost without dither, with it the noise averages
+        // out to it
+        let average = |player: &mut Player, value| {
+            (0..10000)
+                .map(|_| player.output_16_bit(value) as f32)
+                .sum::<f32>()
+                / 10000.0
+        };
+        assert_eq!(average(&mut player, 0x4000), 0.0);
+        player.dither = true;
+        assert!((average(&mut player, 0x4000) - 0.25).abs() < 0.03);
+        assert!((average(&mut player, -0x4000) + 0.25).abs() < 0.03);
+
+        assert_eq!(player.output_16_bit(i32::MAX), i16::MAX);
+        assert_eq!(player.output_16_bit(i32::MIN), i16::MIN);
+    }
+
+    #[test]
+    fn envelope_carry() {
+        for carry in [false, true] {
+            let module = envelope_module(
+                0,
+                Envelope {
+                    enabled: true,
+                    carry,
+                    nodes: vec![(0, 64), (64, 0)],
+                    ..Default::default()
+                },
+            );
+            let mut channel = Channel::new(&module);
+
+            channel.note_on(Some(0), 60, 0);
+            envelope_ticks(&mut channel, 4);
+            channel.note_on(Some(0), 62, 0);
+            let expected = if carry { 60.0 } else { 64.0 };
+            assert_eq!(envelope_ticks(&mut channel, 1), [Some(expected)]);
+
+            // Only a playing note is carried on from
+            channel.playing = false;
+            channel.note_on(Some(0), 62, 0);
+            assert_eq!(envelope_ticks(&mut channel, 1), [Some(64.0)]);
+        }
+    }
+
+    #[test]
+    fn quality_presets() {
+        let module = test_module(vec![empty_pattern(1)]);
+        let mut player = test_player(&module, 48000);
+
+        player.set_quality(Quality::Fast);
+        assert!(matches!(player.interpolation, Interpolation::None));
+        player.set_quality(Quality::Balanced);
+        assert!(matches!(player.interpolation, Interpolation::Linear));
+        player.set_quality(Quality::High);
+        assert!(matches!(player.interpolation, Interpolation::Sinc64));
+    }
+
+    #[test]
+    fn s3m_porta_shares_memory() {
+        let step = |value: f32| 2f32.powf(4.0 * value / 768.0);
+
+        // G00 slides at E05's speed
+        let module = effect_module(
+            PlaybackMode::S3M,
+            false,
+            vec![
+                note(60, VolEffect::None, Effect::None),
+                column(Note::None, Effect::PortaDown(5)),
+                note(62, VolEffect::None, Effect::TonePorta(0)),
+            ],
+        );
+        let rows = channel_per_row(&module);
+        assert!((rows[1].freq - rows[0].freq / step(5.0)).abs() < 0.01);
+        assert!((rows[2].freq - rows[1].freq * step(5.0)).abs() < 0.01);
+
+        // And the other way around, E00 slides at G03's
+        let module = effect_module(
+            PlaybackMode::S3M,
+            false,
+            vec![
+                note(60, VolEffect::None, Effect::None),
+                note(62, VolEffect::None, Effect::TonePorta(3)),
+                column(Note::None, Effect::PortaDown(0)),
+            ],
+        );
+        let rows = channel_per_row(&module);
+        assert!((rows[1].freq - rows[0].freq * step(3.0)).abs() < 0.01);
+        assert!((rows[2].freq - rows[1].freq / step(3.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn porta_note_on_silent_channel_plays() {
+        let mut module = effect_module(
+            PlaybackMode::ITSample,
+            false,
+            vec![note(60, VolEffect::None, Effect::TonePorta(0x10))],
+        );
+        module.samples[0].loop_type = LoopType::Forward;
+        module.samples[0].loop_end = 8;
+        module.samples[0].audio = vec![16000; 8];
+        let mut player = test_player(&module, 48000);
+
+        // Nothing to slide from, so the note starts as if there was no Gxx
+        player.seek(0, 0);
+        assert!(player.channels[0].playing);
+        assert_eq!(player.channels[0].freq, 8363.0);
+        assert!((0..480).any(|_| player.process() != (0, 0)));
+    }
+
+    #[test]
+    fn amiga_slides_clamp() {
+        let mut module = test_module(vec![empty_pattern(1)]);
+        module.linear_freq_slides = false;
+        let mut channel = Channel::new(&module);
+
+        // Down to MIN_FREQ and no further, without ever turning back up
+        channel.note_on(None, 60, 0);
+        let mut last = channel.freq;
+        for _ in 0..20000 {
+            channel.porta_down(false, 0xDF);
+            assert!(channel.freq <= last && channel.freq >= MIN_FREQ);
+            last = channel.freq;
+        }
+        assert_eq!(channel.freq, MIN_FREQ);
+
+        // Up past period 0 stops at MAX_FREQ rather than jumping to the Amiga clock
+        channel.note_on(None, 60, 0);
+        let mut last = channel.freq;
+        for _ in 0..100 {
+            channel.porta_up(false, 0xDF);
+            assert!(channel.freq >= last && channel.freq <= MAX_FREQ);
+            last = channel.freq;
+        }
+        assert!(channel.freq > 500_000.0);
+
+        // Same for a Gxx glide towards an extreme note
+        channel.note_on(None, 119, 0);
+        channel.freq = 8363.0;
+        for _ in 0..100 {
+            channel.slide_to_note(false, 0xFF);
+            assert!(channel.freq <= MAX_FREQ);
+        }
+    }
+
+    #[test]
+    fn volume_slide_at_speed_1() {
+        let mut module = effect_module(
+            PlaybackMode::ITSample,
+            false,
+            vec![
+                note(60, VolEffect::None, Effect::None),
+                column(Note::None, Effect::VolSlide(0x04)),
+                column(Note::None, Effect::VolSlide(0x04)),
+                column(Note::None, Effect::VolSlide(0x04)),
+            ],
+        );
+        module.initial_speed = 1;
+        let mut player = test_player(&module, 48000);
+
+        // Each row's only tick is its first, which still slides
+        let volumes: Vec<f32> = (0..4)
+            .map(|_| {
+                rows_played(&mut player, 1);
+                player.process_tick();
+                player.channels[0].volume
+            })
+            .collect();
+        assert_eq!(volumes, [64.0, 60.0, 56.0, 52.0]);
+    }
+
+    #[test]
+    fn volume_slide_tone_porta_chain() {
+        let step = 2f32.powf(4.0 * 8.0 / 768.0);
+        for mode in [PlaybackMode::S3M, PlaybackMode::ITSample] {
+            let module = effect_module(
+                mode,
+                false,
+                vec![
+                    note(60, VolEffect::None, Effect::None),
+                    note(64, VolEffect::None, Effect::TonePorta(0x08)),
+                    column(Note::None, Effect::VolSlide(0x04)),
+                    column(Note::None, Effect::VolSlideTonePorta(0x02)),
+                    column(Note::None, Effect::VolSlideTonePorta(0)),
+                ],
+            );
+            let rows = channel_per_row(&module);
+            let mode = &module.mode;
+
+            // The volume half goes on from Dxy then Lxy, the portamento half from Gxx
+            let volumes: Vec<f32> = rows.iter().map(|c| c.volume).collect();
+            assert_eq!(volumes, [64.0, 64.0, 60.0, 58.0, 56.0], "{:?}", mode);
+            assert!(
+                (rows[3].freq - rows[2].freq * step).abs() < 0.01,
+                "{:?}",
+                mode
+            );
+            assert!(
+                (rows[4].freq - rows[3].freq * step).abs() < 0.01,
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn speed_and_tempo_on_one_row() {
+        // Whichever channel they're in, both apply before the row's first tick
+        for swap in [false, true] {
+            let mut pattern = empty_pattern(2);
+            pattern[0] = vec![
+                column(Note::None, Effect::SetTempo(0x80)),
+                column(Note::None, Effect::SetSpeed(6)),
+            ];
+            if swap {
+                pattern[0].reverse();
+            }
+            let mut module = test_module(vec![pattern]);
+            module.initial_speed = 3;
+            module.initial_tempo = 200;
+            let mut player = test_player(&module, 48000);
+
+            player.seek(0, 0);
+            assert_eq!((player.speed(), player.tempo()), (6, 128));
+            let tick_length = player.tick_length();
+            let frames = (1..).find(|_| {
+                player.process();
+                player.position() != (0, 0)
+            });
+            assert_eq!(frames, Some(6 * (tick_length + 1)));
+        }
+    }
+
+    #[test]
+    fn degenerate_loop_plays_to_end() {
+        // Zero-length and inverted loops, loaded from a file or set by hand
+        for (loop_type, loop_start, loop_end) in [
+            (LoopType::Forward, 4, 4),
+            (LoopType::PingPong, 4, 4),
+            (LoopType::Forward, 6, 2),
+        ] {
+            let mut module = test_module(vec![empty_pattern(1)]);
+            module.samples[0].loop_type = loop_type;
+            module.samples[0].loop_start = loop_start;
+            module.samples[0].loop_end = loop_end;
+
+            // A sample a step, through all 8 and then silence
+            let mut channel = Channel::new(&module);
+            channel.playing = true;
+            channel.freq = 1.0;
+            let positions: Vec<Option<f64>> = (0..10)
+                .map(|_| {
+                    channel.process(1, Interpolation::None, false, 128);
+                    channel.playing.then_some(channel.position)
+                })
+                .collect();
+            assert_eq!(
+                positions,
+                [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]
+                    .map(Some)
+                    .into_iter()
+                    .chain([None; 3])
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn note_cut_per_format() {
+        for mode in [PlaybackMode::ITSample, PlaybackMode::S3M] {
+            let it = matches!(mode, PlaybackMode::ITSample);
+            let mut module = effect_module(
+                mode,
+                false,
+                vec![
+                    note(60, VolEffect::None, Effect::NoteCut(2)),
+                    Column {
+                        note: Note::None,
+                        instrument: 0,
+                        vol: VolEffect::Volume(48),
+                        effect: Effect::None,
+                    },
+                ],
+            );
+            module.initial_speed = 4;
+            module.samples[0].loop_type = LoopType::Forward;
+            module.samples[0].loop_end = 8;
+            module.samples[0].audio = vec![16000; 8];
+            let mut player = test_player(&module, 48000);
+
+            // IT only silences the note, S3M stops it
+            player.seek(0, 0);
+            for tick in 1..=2 {
+                player.ticks_passed = tick;
+                player.process_tick();
+            }
+            let channel = &player.channels[0];
+            let expected = if it { (true, 0.0) } else { (false, 64.0) };
+            assert_eq!((channel.playing, channel.volume), expected);
+
+            // So in IT the next volume brings it back
+            rows_played(&mut player, 1);
+            assert_eq!(player.channels[0].volume, 48.0);
+            assert_eq!((0..480).any(|_| player.process() != (0, 0)), it);
+        }
+    }
+
+    #[test]
+    fn speed_zero_is_ignored() {
+        let mut pattern = empty_pattern(2);
+        pattern[0][0].effect = Effect::SetSpeed(0);
+        let module = test_module(vec![pattern]);
+        let mut player = test_player(&module, 48000);
+
+        // The row still takes the module's 6 ticks instead of racing through
+        player.seek(0, 0);
+        assert_eq!(player.speed(), 6);
+        let tick_length = player.tick_length();
+        let frames = (1..).find(|_| {
+            player.process();
+            player.position() != (0, 0)
+        });
+        assert_eq!(frames, Some(6 * (tick_length + 1)));
+        assert_eq!(player.position(), (0, 1));
+    }
+
+    #[test]
+    fn sample_global_volume_scales_output() {
+        let output = |global_volume| {
+            let mut module = test_module(vec![empty_pattern(1)]);
+            module.samples[0].global_volume = global_volume;
+            let mut channel = Channel::new(&module);
+            channel.note_on(None, 60, 0);
+            channel.position = 4.0;
+            channel.process(48000, Interpolation::None, false, 128)
+        };
+
+        assert_ne!(output(64), 0);
+        assert_eq!(output(32), output(64) / 2);
+        assert_eq!(output(0), 0);
+    }
+
+    #[test]
+    fn bitcrush_levels() {
+        let module = test_module(vec![empty_pattern(1)]);
+        let mut player = test_player(&module, 48000);
+        player.bitcrush = 4;
+
+        // A full scale sweep comes out in 16 steps
+        let mut levels: Vec<i32> = (i32::MIN as i64..=i32::MAX as i64)
+            .step_by(1 << 20)
+            .map(|x| player.lofi((x as i32, 0)).0)
+            .collect();
+        levels.dedup();
+        assert_eq!(levels.len(), 16);
+    }
+
+    #[test]
+    fn effect_coverage_flags_unsupported() {
+        let module = test_module(vec![vec![
+            vec![column(Note::None, Effect::Arpeggio(0x37))],
+            vec![column(Note::None, Effect::VolSlide(0x04))],
+            vec![note(60, VolEffect::Volume(32), Effect::VolSlide(0x20))],
+        ]]);
+
+        // Each kind once, whatever its value
+        assert_eq!(
+            effect_coverage(&module),
+            [
+                ("Arpeggio".to_string(), false),
+                ("VolSlide".to_string(), true),
+                ("VolVolume".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn duck_gain() {
+        let module = test_module(vec![empty_pattern(1)]);
+        let mut player = test_player(&module, 48000);
+        let frame = (40000, -40000);
+
+        assert_eq!(player.master(frame), frame);
+
+        // Ramped down over 5ms rather than jumping, then held
+        player.set_duck_gain(0.25);
+        let first = player.master(frame);
+        assert!(first.0 < 40000 && first.0 > 10000);
+        for _ in 0..240 {
+            player.master(frame);
+        }
+        assert_eq!(player.master(frame), (10000, -10000));
+
+        // And back up once the duck ends
+        player.set_duck_gain(1.0);
+        for _ in 0..240 {
+            player.master(frame);
+        }
+        assert_eq!(player.master(frame), frame);
+    }
+
+    #[test]
+    fn oscillator_waveforms() {
+        let steps = |waveform| {
+            let mut oscillator = Oscillator::new(0);
+            oscillator.set_waveform(waveform);
+            oscillator.set_parameters(0x11, 1);
+            oscillator.speed = 16;
+            (0..4).map(|_| oscillator.step()).collect::<Vec<f32>>()
+        };
+        assert_eq!(steps(0), [0.0, 64.0, 0.0, -64.0]);
+        assert_eq!(steps(1), [64.0, 32.0, 0.0, -32.0]);
+        assert_eq!(steps(2), [64.0, 64.0, -64.0, -64.0]);
+        // 4..=7 are the same shapes
+        assert_eq!(steps(6), steps(2));
+
+        // Only waveforms 4..=7 carry their position on to the next note
+        let mut oscillator = Oscillator::new(0);
+        oscillator.set_parameters(0x41, 1);
+        for (waveform, position) in [(0, 0), (4, 8), (3, 0), (7, 8)] {
+            oscillator.set_waveform(waveform);
+            oscillator.position = 0;
+            oscillator.step();
+            oscillator.step();
+            oscillator.note_on();
+            assert_eq!(oscillator.position, position, "S3{:X}", waveform);
+        }
+    }
+
+    #[test]
+    fn random_waveform_per_channel() {
+        let module = test_module(vec![empty_pattern(1)]);
+        let player = test_player(&module, 48000);
+        let cycles = |mut oscillator: Oscillator, count: usize| {
+            oscillator.set_waveform(3);
+            oscillator.set_parameters(0x11, 1);
+            (0..count).map(|_| oscillator.step()).collect::<Vec<f32>>()
+        };
+
+        let first = cycles(player.channels[0].vibrato, 128);
+        assert!(first.iter().all(|x| (-64.0..=64.0).contains(x)));
+        // Doesn't repeat every cycle of 64 steps
+        assert_ne!(first[..64], first[64..]);
+        // Nor is it the same on every channel or oscillator
+        assert_ne!(first, cycles(player.channels[1].vibrato, 128));
+        assert_ne!(first, cycles(player.channels[0].tremolo, 128));
     }
 }
@@ -1,11 +1,12 @@
 mod engine;
 
 use engine::format_it::ITModule;
-use engine::player::{Interpolation, Player};
+use engine::player::{effect_coverage, Interpolation, Player, Quality};
 
 use crate::engine::module::ModuleInterface;
 
 use clap::Parser;
+use std::io::Write;
 
 #[derive(Parser, Debug)]
 #[command(name = "Rust module player")]
@@ -16,44 +17,156 @@ struct Args {
     #[arg(short, long, value_enum, default_value_t = Interpolation::Linear)]
     interpolation: Interpolation,
 
+    #[arg(short, long, value_enum)]
+    quality: Option<Quality>,
+
     #[arg(short, long, default_value_t = 0)]
     position: u8,
+
+    #[arg(short, long)]
+    tempo: Option<u8>,
+
+    #[arg(short, long)]
+    speed: Option<u8>,
+
+    // Write the song to a WAV file instead of playing it
+    #[arg(short, long)]
+    wav: Option<String>,
+
+    // Dither the WAV export down to 16 bits
+    #[arg(long)]
+    dither: bool,
+
+    // Longest a WAV export can run, for songs that loop forever
+    #[arg(long, default_value_t = 600)]
+    max_seconds: u32,
+
+    // Swap a sample (1-based) for raw signed 16-bit little endian mono PCM, as INDEX:FILE
+    #[arg(long)]
+    replace_sample: Option<String>,
+
+    // Sample rate of the replacement PCM, it then plays at that rate on C-5
+    #[arg(long)]
+    pcm_rate: Option<u32>,
+
+    // Resample the replacement PCM to the old sample's rate instead of retuning it
+    #[arg(long)]
+    resample: bool,
+
+    // Write the module back out as IT instead of playing it
+    #[arg(long)]
+    save: Option<String>,
 }
 
 fn main() {
     let args = Args::parse();
 
     let file = std::fs::File::open(args.file).unwrap();
-    let module: ITModule = ITModule::load(file).unwrap_or_else(|e| {
+    let mut module: ITModule = ITModule::load(file).unwrap_or_else(|e| {
         eprintln!("{}", e);
         std::process::exit(1)
     });
-    let binding = module.module();
+    let mut binding = module.module();
+
+    if let Some(replacement) = args.replace_sample {
+        let Some((index, path)) = replacement.split_once(':') else {
+            eprintln!("--replace-sample takes INDEX:FILE");
+            std::process::exit(1)
+        };
+        let bytes = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1)
+        });
+        let audio = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+
+        let replaced = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| binding.replace_sample(i, audio, args.pcm_rate, args.resample));
+        if replaced.is_none() {
+            eprintln!("There's no sample {}", index);
+            std::process::exit(1)
+        }
+    }
+
+    if let Some(path) = args.save {
+        module.apply(&binding);
+        let result = std::fs::File::create(&path)
+            .and_then(|file| module.save(std::io::BufWriter::new(file)));
+        if let Err(e) = result {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1)
+        }
+        return;
+    }
 
     let mut player: Player = Player::from_module(&binding, 48000);
     player.interpolation = args.interpolation;
-    player.current_position = args.position;
-    player.current_pattern = player.module.playlist[player.current_position as usize];
+    if let Some(quality) = args.quality {
+        player.set_quality(quality);
+    }
+    // Set before seeking, so Axx/Txx on the starting row still win
+    if let Some(tempo) = args.tempo {
+        player.set_tempo(tempo);
+    }
+    if let Some(speed) = args.speed {
+        player.set_speed(speed);
+    }
+    if !player.seek(args.position as usize, 0) {
+        eprintln!("Position {} is past the end of the song", args.position);
+        std::process::exit(1)
+    }
+
+    if let Some(path) = args.wav {
+        player.dither = args.dither;
+        let result = std::fs::File::create(&path).and_then(|file| {
+            let mut writer = std::io::BufWriter::new(file);
+            player.render_to_wav(&mut writer, args.max_seconds)?;
+            writer.flush()
+        });
+        if let Err(e) = result {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1)
+        }
+        return;
+    }
 
     let sdl_context = sdl2::init().unwrap();
     let audio_subsystem = sdl_context.audio().unwrap();
 
     let spec = sdl2::audio::AudioSpecDesired {
         freq: Some(48000),
-        channels: Some(1),
+        channels: Some(2),
         samples: Some(512),
     };
 
-    let device = audio_subsystem
+    let mut device = audio_subsystem
         .open_playback(None, &spec, |_| player)
         .unwrap();
 
     println!("Module name: {}", binding.name);
+    let (mode, tracker) = binding.format();
+    println!("Format: {:?} ({})", mode, tracker);
+
+    let unsupported: Vec<String> = effect_coverage(&binding)
+        .into_iter()
+        .filter(|(_, supported)| !supported)
+        .map(|(name, _)| name)
+        .collect();
+    if !unsupported.is_empty() {
+        println!("Unsupported effects: {}", unsupported.join(", "));
+    }
     device.resume();
 
     ctrlc::set_handler(move || std::process::exit(0)).expect("error listening to interrupt");
 
-    loop {}
+    while !device.lock().is_finished() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
 }
 
 /* fn format_note(note: u8) -> String {
@@ -100,7 +213,32 @@ fn format_col(row: &ITColumn) -> String {
 fn main() {
     let file = std::fs::File::open("/home/polyzium/Downloads/Siren - NYC Streets.it").unwrap();
     let module: ITModule = ITModule::load(file);
-    let binding = module.module();
+    let mut binding = module.module();
+
+    if let Some(replacement) = args.replace_sample {
+        let Some((index, path)) = replacement.split_once(':') else {
+            eprintln!("--replace-sample takes INDEX:FILE");
+            std::process::exit(1)
+        };
+        let bytes = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1)
+        });
+        let audio = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+
+        let replaced = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| binding.replace_sample(i, audio, args.pcm_rate, args.resample));
+        if replaced.is_none() {
+            eprintln!("There's no sample {}", index);
+            std::process::exit(1)
+        }
+    }
 
     let player: Player = Player::from_module(&binding);
     println!("{:?}", player.module.samples[0]);